@@ -28,15 +28,75 @@ window.onload = () => {
       indentWithTabs: false,
       renderingConfig: {
         singleLineBreaks: false,
-        codeSyntaxHighlighting: true,
+        codeSyntaxHighlighting: false,
       },
       status: false,
       tabSize: 4,
       element: el,
     });
+
+    // attach files by dragging, pasting, or picking them
+    let cm = simplemde.codemirror;
+    cm.on("drop", (cm, e) => {
+      if (e.dataTransfer.files.length == 0) return;
+      e.preventDefault();
+      cm.setCursor(cm.coordsChar({ left: e.clientX, top: e.clientY }));
+      uploadFiles(cm, e.dataTransfer.files);
+    });
+    cm.on("paste", (cm, e) => {
+      if (!e.clipboardData || e.clipboardData.files.length == 0) return;
+      e.preventDefault();
+      uploadFiles(cm, e.clipboardData.files);
+    });
+    let upload = $("#upload");
+    if (upload) {
+      upload.addEventListener("change", () => {
+        uploadFiles(cm, upload.files);
+        upload.value = "";
+      });
+    }
   }
 };
 
+/* upload files to the page being edited, then insert links to them
+   at the cursor. vial only handles text request bodies, so the file
+   contents are sent base64 encoded. */
+let uploadFiles = (cm, files) => {
+  let page = $("#upload-page").value;
+  Array.from(files).forEach((file) => {
+    let reader = new FileReader();
+    reader.onload = () => {
+      let data = reader.result.split(",")[1] || "";
+      let boundary = "----deadwiki" + Math.random().toString(16).slice(2);
+      let name = (file.name || "pasted.png").replace(/["\r\n]/g, "");
+      let body =
+        "--" + boundary + "\r\n" +
+        'Content-Disposition: form-data; name="page"\r\n\r\n' +
+        page + "\r\n" +
+        "--" + boundary + "\r\n" +
+        'Content-Disposition: form-data; name="file"; filename="' + name + '"\r\n' +
+        "Content-Type: " + (file.type || "application/octet-stream") + "\r\n" +
+        "Content-Transfer-Encoding: base64\r\n\r\n" +
+        data + "\r\n" +
+        "--" + boundary + "--\r\n";
+
+      var xhr = new XMLHttpRequest();
+      xhr.open("POST", "/upload");
+      xhr.setRequestHeader("Content-Type", "multipart/form-data; boundary=" + boundary);
+      xhr.onload = () => {
+        if (xhr.status == 200) {
+          cm.replaceSelection(xhr.responseText + "\n");
+          cm.focus();
+        } else {
+          alert("Upload failed: " + (xhr.responseText || xhr.status));
+        }
+      };
+      xhr.send(body);
+    };
+    reader.readAsDataURL(file);
+  });
+};
+
 /* keydown */
 document.onkeydown = (e) => {
   e = e || window.event || {};
@@ -130,6 +190,41 @@ if (jumpLink) {
   });
 }
 
+/* tick task list checkboxes on the page itself */
+var pageBody = $("#page-body");
+if (pageBody) {
+  pageBody.addEventListener("change", (e) => {
+    let box = e.target;
+    if (!box.dataset || box.dataset.task === undefined) return;
+    let xhr = new XMLHttpRequest();
+    xhr.open("POST", "/task/" + pageBody.dataset.page);
+    xhr.setRequestHeader("Content-Type", "application/x-www-form-urlencoded");
+    xhr.onload = () => {
+      if (xhr.status == 200) {
+        pageBody.dataset.version = xhr.responseText;
+      } else {
+        box.checked = !box.checked;
+        alert(xhr.responseText || "Couldn't save: " + xhr.status);
+      }
+    };
+    xhr.send(
+      "task=" + box.dataset.task +
+      "&done=" + box.checked +
+      "&version=" + pageBody.dataset.version
+    );
+  });
+}
+
+/* template picker on the new page form */
+var templatePicker = $("#template-picker");
+if (templatePicker) {
+  templatePicker.addEventListener("change", () => {
+    let form = $("#template-form");
+    form.querySelector("[name=name]").value = $("#focused").value;
+    form.submit();
+  });
+}
+
 /* jump to page (ctrl-j) */
 window.WIKI_PAGES = [];
 var first = true;
@@ -1,3 +1,4 @@
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(bundle_assets)");
     vial::bundle_assets!("./assets").unwrap();
 }
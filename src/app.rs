@@ -1,8 +1,15 @@
 use {
-    crate::{db::ReqWithDB, markdown, Hatter},
+    crate::{
+        db::{ReqWithDB, Revision},
+        diff::{self, Granularity, Layout},
+        journal, markdown, template, upload,
+        utils::{html_encode, url_encode},
+        Hatter,
+    },
+    chrono::{Datelike, Local, NaiveDate},
     hatter,
     std::{collections::HashMap, io, time::Instant},
-    vial::prelude::*,
+    vial::{prelude::*, util},
 };
 
 routes! {
@@ -16,10 +23,29 @@ routes! {
     POST "/new" => create;
 
     GET "/search" => search;
+    GET "/tags" => tags;
+
+    GET "/today" => today;
+    GET "/journal" => journal;
 
     GET "/edit/*name" => edit;
     POST "/edit/*name" => update;
 
+    POST "/rename/*name" => rename;
+
+    POST "/upload" => upload;
+
+    POST "/task/*name" => toggle_task;
+
+    GET "/trash" => trash;
+    POST "/delete/*name" => delete;
+    POST "/restore/*name" => restore;
+    POST "/purge/*name" => purge;
+
+    GET "/history/*name" => history;
+    POST "/history/*name" => restore_revision;
+    GET "/diff/*name" => diff;
+
     GET "/toggle-ui-mode" => toggle_ui_mode;
 
     GET "/*name" => show;
@@ -36,23 +62,143 @@ macro_rules! unwrap_or_404 {
     };
 }
 
+/// Search for pages by #tag with ?tag= or by their content with ?q=
 fn search(req: Request) -> io::Result<impl Responder> {
     let mut env = Hatter::new();
-    let tag = unwrap_or_404!(req.query("tag"));
-    env.set("tag", tag);
-    env.set("pages", req.db().find_pages_with_tag(tag)?);
+    let query = util::decode_form_value(req.query("q").unwrap_or(""));
+    let query = query.trim();
+    env.set("query", html_encode(query));
+    env.set("tag?", false);
+    env.set("searched?", false);
+
+    if let Some(tag) = req.query("tag") {
+        let tag = util::decode_form_value(tag);
+        env.set("tag?", true);
+        env.set("tag", html_encode(&tag));
+        env.set("pages", req.db().find_pages_with_tag(&tag)?);
+    } else {
+        let results = req.db().search(query)?;
+        let results = results.iter().map(|hit| {
+            let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+            map.insert("title", html_encode(&hit.page.title()).into());
+            map.insert("url", html_encode(&url_encode(&hit.page.url())).into());
+            map.insert("snippet", hit.snippet.clone().into());
+            map
+        });
+        env.set("results", results.collect::<Vec<_>>());
+        env.set("searched?", !query.is_empty());
+    }
+
     req.render("Search", env.render("html/search.hat")?)
 }
 
+/// Every #tag, nested under its parent, with how many pages use it.
+fn tags(req: Request) -> io::Result<impl Responder> {
+    let mut env = Hatter::new();
+    let tags = req.db().tag_tree()?;
+    let tags = tags.iter().map(|tag| {
+        let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+        map.insert("label", html_encode(tag.label()).into());
+        map.insert(
+            "url",
+            format!("/search?tag={}", html_encode(&url_encode(&tag.name))).into(),
+        );
+        map.insert("depth", tag.depth.into());
+        map.insert("count", tag.count.into());
+        map
+    });
+    env.set("tags", tags.collect::<Vec<_>>());
+    req.render("Tags", env.render("html/tags.hat")?)
+}
+
+/// New page form. Starts with the template from ?template= or the
+/// default template for the page's directory, if there is one.
 fn new(req: Request) -> io::Result<impl Responder> {
     let mut env = Hatter::new();
+    let name = util::decode_form_value(req.query("name").unwrap_or(""));
+    let template = match req.query("template") {
+        Some(t) => util::decode_form_value(t),
+        None => req.db().default_template(&name).unwrap_or_default(),
+    };
+    let body = match req.db().template(&template) {
+        Some(src) => template::expand(&src, &name, Local::now().naive_local()),
+        None => format!("# {}", name),
+    };
+
+    let templates = req.db().templates()?;
+    env.set("templates?", !templates.is_empty());
+    let templates = templates.iter().map(|t| {
+        let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+        map.insert("name", html_encode(t).into());
+        map.insert("selected?", (*t == template).into());
+        map
+    });
+    env.set("templates", templates.collect::<Vec<_>>());
     env.set("error?", false);
-    env.set("name", req.query("name"));
+    env.set("name", html_encode(&name));
+    env.set("page-body", html_encode(&body));
+    req.render("New Page", env.render("html/new.hat")?)
+}
+
+/// Open today's journal page, creating it if needed.
+fn today(req: Request) -> io::Result<impl Responder> {
+    let page = req.db().journal_entry(Local::now().date_naive())?;
+    redirect_to(page.url())
+}
+
+/// Calendar of journal pages for a month: /journal?month=2020-06
+fn journal(req: Request) -> io::Result<impl Responder> {
+    let today = Local::now().date_naive();
+    let month = req
+        .query("month")
+        .and_then(|m| NaiveDate::parse_from_str(&format!("{}-01", m), "%Y-%m-%d").ok())
+        .unwrap_or(today);
+    let entries = req.db().journal()?;
+
+    let weeks = journal::calendar(month, &entries).into_iter().map(|week| {
+        week.into_iter()
+            .map(|day| {
+                let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+                if let Some(day) = day {
+                    let name = journal::page_name(day.date);
+                    let (class, url) = if day.entry {
+                        ("entry", format!("/{}", name))
+                    } else {
+                        ("", format!("/new?name={}", name))
+                    };
+                    let class = if day.date == today {
+                        format!("{} today", class).trim().to_string()
+                    } else {
+                        class.to_string()
+                    };
+                    map.insert("day?", true.into());
+                    map.insert("day", (day.date.day() as usize).into());
+                    map.insert("class", class.into());
+                    map.insert("url", url.into());
+                } else {
+                    map.insert("day?", false.into());
+                }
+                map
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut env = Hatter::new();
+    let (prev, next) = journal::prev_next_month(month);
+    env.set("month", month.format("%B %Y").to_string());
     env.set(
-        "page-body",
-        format!("# {}", req.query("name").unwrap_or("")),
+        "prev-url",
+        format!("/journal?month={}", prev.format("%Y-%m")),
     );
-    req.render("New Page", env.render("html/new.hat")?)
+    env.set(
+        "next-url",
+        format!("/journal?month={}", next.format("%Y-%m")),
+    );
+    env.set("prev", prev.format("%b").to_string());
+    env.set("next", next.format("%b").to_string());
+    env.set("weeks", weeks.collect::<Vec<_>>());
+    env.set("entries", entries.len());
+    req.render("Journal", env.render("html/journal.hat")?)
 }
 
 /// Render the index page which lists all wiki pages or displays your
@@ -89,10 +235,10 @@ fn create(req: Request) -> io::Result<impl Responder> {
         redirect_to(page.url())
     } else {
         let mut env = Hatter::new();
-        env.set("name", name);
+        env.set("name", html_encode(name));
         env.set("error?", true);
         env.set("error", "Wiki page with that name already exists.");
-        env.set("page-body", req.form("markdown").unwrap_or(""));
+        env.set("page-body", html_encode(req.form("markdown").unwrap_or("")));
         req.render("New Page", env.render("html/new.hat")?)
     }
 }
@@ -108,26 +254,45 @@ fn recent(req: Request) -> io::Result<impl Responder> {
 fn jump(req: Request) -> io::Result<impl Responder> {
     let mut env = Hatter::new();
 
-    let pages = req.db().pages()?;
-    let pages = pages.iter().enumerate().map(|(i, p)| {
+    let all = req.db().pages()?;
+    let pages = all.iter().enumerate().map(|(i, p)| {
         let mut map: HashMap<&str, hatter::Value> = HashMap::new();
         map.insert("id", i.into());
-        map.insert("name", p.title().into());
-        map.insert("url", p.url().into());
+        map.insert("name", html_encode(&p.title()).into());
+        map.insert("url", html_encode(&url_encode(&p.url())).into());
         map
     });
 
-    let idx = pages.len();
+    // aliases jump to the page they belong to
+    let mut idx = pages.len();
+    let mut aliases = vec![];
+    for (alias, name, title) in req.db().alias_titles()? {
+        let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+        map.insert("id", (idx + aliases.len()).into());
+        let label = format!("{} → {}", alias, title);
+        map.insert("name", html_encode(&label).into());
+        let url = format!("/{}", name);
+        map.insert("url", html_encode(&url_encode(&url)).into());
+        aliases.push(map);
+    }
+    idx += aliases.len();
+
     let tags = req.db().tags()?;
     let tags = tags.iter().enumerate().map(|(i, tag)| {
         let mut map: HashMap<&str, hatter::Value> = HashMap::new();
         map.insert("id", (idx + i).into());
-        map.insert("name", format!("#{}", tag).into());
-        map.insert("url", format!("/search?tag={}", tag).into());
+        map.insert("name", format!("#{}", html_encode(tag)).into());
+        map.insert(
+            "url",
+            format!("/search?tag={}", html_encode(&url_encode(tag))).into(),
+        );
         map
     });
 
-    env.set("pages", pages.chain(tags).collect::<Vec<_>>());
+    env.set(
+        "pages",
+        pages.chain(aliases).chain(tags).collect::<Vec<_>>(),
+    );
     req.render("Jump to Wiki Page", env.render("html/jump.hat")?)
 }
 
@@ -138,12 +303,23 @@ fn update(req: Request) -> io::Result<impl Responder> {
 }
 
 fn edit(req: Request) -> io::Result<impl Responder> {
-    let mut env = Hatter::new();
     let name = unwrap_or_404!(req.arg("name"));
-    let page = unwrap_or_404!(req.db().find(name));
-    env.set("page", page);
-    env.set("conflicts", req.query("conflicts").is_some());
-    req.render("Edit", env.render("html/edit.hat")?)
+    show_edit(&req, name, None)
+}
+
+/// Move a page to a new name and fix up all the links to it.
+fn rename(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/rename/"));
+    let new_name = unwrap_or_404!(req.form("name"));
+    match req.db().rename(&name, new_name) {
+        Ok(page) => redirect_to(page.url()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => show_edit(
+            &req,
+            &name,
+            Some("Wiki page with that name already exists."),
+        ),
+        Err(e) => Err(e),
+    }
 }
 
 fn show(req: Request) -> io::Result<impl Responder> {
@@ -151,8 +327,246 @@ fn show(req: Request) -> io::Result<impl Responder> {
     if name.ends_with(".md") || !name.contains('.') {
         show_page(&req, name)
     } else {
-        Ok(Response::from_file(&req.db().absolute_path(name)))
+        let response = Response::from_file(&req.db().absolute_path(name));
+        if !upload::is_attachment(name) {
+            Ok(response)
+        } else if upload::is_inline(name) {
+            Ok(response.with_header("X-Content-Type-Options", "nosniff"))
+        } else {
+            Ok(response
+                .with_header("X-Content-Type-Options", "nosniff")
+                .with_header("Content-Disposition", "attachment"))
+        }
+    }
+}
+
+/// Tick or untick a task list checkbox on a page. Refused if the page
+/// changed since it was shown, so we don't flip the wrong task.
+fn toggle_task(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/task/"));
+    let page = unwrap_or_404!(req.db().find(&name));
+    let task = unwrap_or_404!(req.form("task").and_then(|t| t.parse().ok()));
+    let done = req.form("done") == Some("true");
+
+    if req.form("version") != Some(page.version().as_str()) {
+        return Ok(Response::from(409)
+            .with_text("This page has changed since you loaded it. Reload it and try again."));
+    }
+    let body = unwrap_or_404!(markdown::toggle_task(&page.body(), task, done));
+    let page = req.db().update(page.name(), &body)?;
+    Ok(Response::from_text(page.version()))
+}
+
+/// Move a page to the trash.
+fn delete(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/delete/"));
+    req.db().delete(&name)?;
+    redirect_to("/trash")
+}
+
+/// List deleted pages.
+fn trash(req: Request) -> io::Result<impl Responder> {
+    let mut env = Hatter::new();
+    env.set("pages", req.db().trash()?);
+    env.set("error?", req.query("exists").is_some());
+    env.set("error", "Wiki page with that name already exists.");
+    req.render("Trash", env.render("html/trash.hat")?)
+}
+
+/// Bring a page back from the trash.
+fn restore(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/restore/"));
+    match req.db().restore(&name) {
+        Ok(page) => redirect_to(page.url()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => redirect_to("/trash?exists=true"),
+        Err(e) => Err(e),
+    }
+}
+
+/// Delete a page from the trash for good.
+fn purge(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/purge/"));
+    req.db().purge(&name)?;
+    redirect_to("/trash")
+}
+
+/// Attach a file to a page. Expects `multipart/form-data` with a
+/// `page` name and a `file`, and responds with the Markdown to link
+/// to the saved file.
+fn upload(req: Request) -> io::Result<impl Responder> {
+    // check before we parse and decode anything
+    let size = req
+        .header("content-length")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    if size > upload::MAX_BODY {
+        return Ok(Response::from_code(413).with_text(format!(
+            "Too big: {} bytes (max {})",
+            size,
+            upload::MAX_BODY
+        )));
+    }
+    let content_type = req.header("content-type").unwrap_or_default();
+    let parts = match upload::parse(&content_type, req.body()) {
+        Ok(parts) => parts,
+        Err(e) => return Ok(Response::from_code(400).with_text(e.to_string())),
+    };
+    let page = parts.iter().find(|p| p.name == "page");
+    let file = parts.iter().find(|p| p.name == "file");
+    let (page, file) = match (page, file) {
+        (Some(page), Some(file)) => (String::from_utf8_lossy(&page.data), file),
+        _ => return Ok(Response::from_code(400).with_text("Need a page and a file.")),
+    };
+
+    let filename = file.filename.as_deref().unwrap_or("");
+    match req.db().attach(&page, filename, &file.data) {
+        Ok(url) => {
+            let filename = url.rsplit('/').next().unwrap_or(filename);
+            Ok(Response::from_text(upload::markdown_link(filename, &url)))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(response_404()),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            Ok(Response::from_code(413).with_text(e.to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// List a page's revisions, or show an old revision of it when the
+/// path ends in a commit hash: /history/some/page/8f3c2a1
+fn history(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/history/"));
+    if req.db().exists(&name) {
+        return show_history(&req, &name);
+    }
+
+    let (name, rev) = unwrap_or_404!(name.rsplit_once('/'));
+    let page = unwrap_or_404!(req.db().find(name));
+    let (rev, body) = match req.db().revision(name, rev) {
+        Ok(found) => found,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(response_404()),
+        Err(e) => return Err(e),
+    };
+
+    let mut env = Hatter::new();
+    let title = format!("{} ({})", page.title(), rev.short_id());
+    env.set("page", page);
+    env.set("rev", revision_map(&rev));
+    env.set("body", body);
+    set_markdown(&mut env, req.db().markdown_context()?);
+    req.render(&title, env.render("html/revision.hat")?)
+}
+
+/// Replace a page's content with an old revision of it.
+fn restore_revision(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/history/"));
+    let (name, rev) = unwrap_or_404!(name.rsplit_once('/'));
+    let (_, body) = req.db().revision(name, rev)?;
+    let page = req.db().update(name, &body)?;
+    redirect_to(page.url())
+}
+
+fn show_history(req: &Request, name: &str) -> io::Result<Response> {
+    let mut env = Hatter::new();
+    let page = unwrap_or_404!(req.db().find(name));
+    let revisions = req.db().history(name)?;
+    env.set("is_git?", req.db().is_git());
+    let revisions = revisions.iter().enumerate().map(|(i, rev)| {
+        let mut map = revision_map(rev);
+        let prev = revisions.get(i + 1).map(|r| r.id.as_str()).unwrap_or("");
+        map.insert("prev-id", prev.into());
+        map.insert("first?", prev.is_empty().into());
+        map
+    });
+    env.set("revisions", revisions.collect::<Vec<_>>());
+    let title = format!("History of {}", page.title());
+    env.set("page", page);
+    req.render(&title, env.render("html/history.hat")?)
+}
+
+/// Compare two revisions of a page, or a revision and the current
+/// version: /diff/some/page?from=8f3c2a1&to=current
+///
+/// Add by=line to only highlight whole lines and layout=inline to show
+/// everything in one column.
+fn diff(req: Request) -> io::Result<impl Responder> {
+    let name = unwrap_or_404!(splat_arg(&req, "/diff/"));
+    let page = unwrap_or_404!(req.db().find(&name));
+    let from = unwrap_or_404!(req.query("from"));
+    let to = req.query("to").unwrap_or("current");
+    let (from_label, old) = unwrap_or_404!(revision_body(&req, &name, from)?);
+    let (to_label, new) = unwrap_or_404!(revision_body(&req, &name, to)?);
+
+    let granularity = match req.query("by") {
+        Some("line") => Granularity::Line,
+        _ => Granularity::Word,
+    };
+    let layout = match req.query("layout") {
+        Some("inline") => Layout::Inline,
+        _ => Layout::SideBySide,
+    };
+
+    let mut env = Hatter::new();
+    let url = format!(
+        "/diff/{}?from={}&to={}",
+        html_encode(&url_encode(page.name())),
+        html_encode(&url_encode(from)),
+        html_encode(&url_encode(to))
+    );
+    let by = if granularity == Granularity::Line {
+        "line"
+    } else {
+        "word"
+    };
+    let view = if layout == Layout::Inline {
+        "inline"
+    } else {
+        "side"
+    };
+    env.set("lines-url", format!("{}&by=line&layout={}", url, view));
+    env.set("words-url", format!("{}&by=word&layout={}", url, view));
+    env.set("inline-url", format!("{}&by={}&layout=inline", url, by));
+    env.set("side-url", format!("{}&by={}&layout=side", url, by));
+    env.set("from", from_label);
+    env.set("to", to_label);
+    env.set("diff", diff::to_html(&old, &new, granularity, layout));
+    let title = format!("Changes to {}", page.title());
+    env.set("page", page);
+    req.render(&title, env.render("html/diff.hat")?)
+}
+
+/// A label and the content for a page at a revision, or "current" for
+/// what's on disk now. None if there's no such revision.
+fn revision_body(req: &Request, name: &str, rev: &str) -> io::Result<Option<(String, String)>> {
+    if rev == "current" {
+        return Ok(req.db().find(name).map(|p| ("current".into(), p.body())));
     }
+    match req.db().revision(name, rev) {
+        Ok((rev, body)) => Ok(Some((rev.short_id().to_string(), body))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Hatter-friendly version of a Revision.
+fn revision_map(rev: &Revision) -> HashMap<&'static str, hatter::Value> {
+    let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+    map.insert("id", rev.id.clone().into());
+    map.insert("short-id", rev.short_id().into());
+    map.insert("author", html_encode(&rev.author).into());
+    map.insert("date", rev.date.clone().into());
+    map.insert("message", html_encode(&rev.message).into());
+    map
+}
+
+fn show_edit(req: &Request, name: &str, error: Option<&str>) -> io::Result<Response> {
+    let mut env = Hatter::new();
+    let page = unwrap_or_404!(req.db().find(name));
+    env.set("page", page);
+    env.set("conflicts", req.query("conflicts").is_some());
+    env.set("error?", error.is_some());
+    env.set("error", error.unwrap_or(""));
+    req.render("Edit", env.render("html/edit.hat")?)
 }
 
 fn show_index(req: &Request) -> io::Result<Response> {
@@ -178,7 +592,12 @@ fn show_index(req: &Request) -> io::Result<Response> {
 
 fn show_page(req: &Request, name: &str) -> io::Result<Response> {
     let mut env = Hatter::new();
-    let page = unwrap_or_404!(req.db().find(name.trim_end_matches(".md")));
+    let name = name.trim_end_matches(".md");
+    let page = unwrap_or_404!(req.db().find(name));
+    if page.name() != name {
+        // found by alias
+        return redirect_to(page.url());
+    }
     if page.has_conflict() {
         return redirect_to(format!("/edit{}?conflicts=true", page.url()));
     }
@@ -189,10 +608,13 @@ fn show_page(req: &Request, name: &str) -> io::Result<Response> {
         if path.contains('/') {
             format!(
                 "/new?name={}/",
-                path.split('/')
-                    .take(path.matches('/').count())
-                    .collect::<Vec<_>>()
-                    .join("/")
+                html_encode(
+                    &path
+                        .split('/')
+                        .take(path.matches('/').count())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                )
             )
         } else {
             "/new".into()
@@ -200,20 +622,77 @@ fn show_page(req: &Request, name: &str) -> io::Result<Response> {
     );
 
     let title = page.title().clone();
-    let names = req.db().names()?;
+    let mut context = req.db().markdown_context()?;
+    context.page = Some(page.name().to_string());
+    env.set("backlinks", req.db().backlinks(page.name())?);
+
+    // outline of the page's headings, for the sidebar
+    let toc = markdown::toc(&page.body());
+    env.set("toc?", toc.len() > 1);
+    let toc = toc.iter().map(|heading| {
+        let mut map: HashMap<&str, hatter::Value> = HashMap::new();
+        map.insert("level", (heading.level as usize).into());
+        map.insert("title", html_encode(&heading.title).into());
+        map.insert("url", format!("#{}", markdown::html_id(&heading.id)).into());
+        map
+    });
+    env.set("toc", toc.collect::<Vec<_>>());
+
+    // previous and next entries for journal pages
+    let date = journal::date(page.name());
+    env.set("journal?", date.is_some());
+    if let Some(date) = date {
+        let entries = req.db().journal()?;
+        let idx = entries.binary_search(&date).unwrap_or_else(|i| i);
+        let prev = idx.checked_sub(1).and_then(|i| entries.get(i));
+        let next = entries.iter().find(|d| **d > date);
+        env.set("prev?", prev.is_some());
+        env.set("next?", next.is_some());
+        env.set(
+            "prev-url",
+            prev.map(|d| format!("/{}", journal::page_name(*d))),
+        );
+        env.set(
+            "next-url",
+            next.map(|d| format!("/{}", journal::page_name(*d))),
+        );
+        env.set(
+            "calendar-url",
+            format!("/journal?month={}", date.format("%Y-%m")),
+        );
+    }
 
+    env.set("git?", req.db().is_git());
     env.set("page", page);
+    set_markdown(&mut env, context);
+    req.render(&title, env.render("html/show.hat")?)
+}
+
+/// Add the `markdown()` function to a template, which turns wiki
+/// Markdown into HTML.
+fn set_markdown(env: &mut Hatter, context: markdown::Context) {
     env.set("markdown", move |args: hatter::Args| {
         let src = args.need_string(0).unwrap();
-        Ok(markdown::to_html(&src, &names).into())
+        Ok(markdown::to_html(src, &context).into())
     });
-    req.render(&title, env.render("html/show.hat")?)
 }
 
 fn response_404() -> Response {
     Response::from(404).with_asset("html/404.html")
 }
 
+/// The `*name` part of a route like "/rename/*name". Vial finds it by
+/// searching the path for the first matching segment, which goes
+/// wrong for pages like "/rename/a", so we strip the prefix instead.
+fn splat_arg(req: &Request, prefix: &str) -> Option<String> {
+    let name = req.path().strip_prefix(prefix)?;
+    if name.is_empty() {
+        None
+    } else {
+        util::percent_decode(name)
+    }
+}
+
 // Clean up POST'd markdown data - mostly by removing \r, which HTTP loves.
 fn markdown_post_data(req: &Request) -> String {
     req.form("markdown").unwrap_or("").replace('\r', "")
@@ -230,7 +709,7 @@ trait Render {
 impl Render for Request {
     fn render<S: AsRef<str>>(&self, title: &str, body: S) -> Result<Response, io::Error> {
         let mut env = Hatter::new();
-        env.set("title", title);
+        env.set("title", html_encode(title));
         env.set("body", body.as_ref());
         env.set("dark-mode?", matches!(self.cookie("ui-mode"), Some("dark")));
         let start = Instant::now();
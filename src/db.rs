@@ -1,15 +1,29 @@
 use {
-    crate::Page,
+    crate::{
+        index::{self, Entry, Index},
+        journal, markdown,
+        search::{Hit, SearchIndex},
+        tags, template, upload, watch, Page,
+    },
+    chrono::{Local, NaiveDate},
     std::{
         collections::HashMap,
-        fs::{self, File},
+        fs::{self, File, OpenOptions},
         io::{self, Write},
         path::{Path, PathBuf},
+        process::Command,
+        sync::{Arc, RwLock},
     },
 };
 
 pub type Result<T> = std::result::Result<T, std::io::Error>;
 
+/// Deleted pages are moved here, inside the wiki root.
+const TRASH_DIR: &str = ".trash";
+
+/// Diagrams are cached here, inside the wiki root.
+const DIAGRAM_CACHE_DIR: &str = ".cache/diagrams";
+
 pub trait ReqWithDB {
     fn db(&self) -> &DB;
 }
@@ -20,8 +34,30 @@ impl ReqWithDB for vial::Request {
     }
 }
 
+/// A commit that touched a wiki page.
+#[derive(Debug)]
+pub struct Revision {
+    pub id: String,
+    pub author: String,
+    pub date: String,
+    pub message: String,
+    /// Path to the page at this revision, relative to the wiki root.
+    /// Can change if the page was renamed.
+    pub path: String,
+}
+
+impl Revision {
+    /// Abbreviated commit hash.
+    pub fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(7)]
+    }
+}
+
 pub struct DB {
     root: String,
+    index: Arc<Index>,
+    search: RwLock<SearchIndex>,
+    raw_html: bool,
 }
 
 unsafe impl Sync for DB {}
@@ -32,9 +68,25 @@ impl DB {
     pub fn new<S: AsRef<str>>(root: S) -> DB {
         DB {
             root: root.as_ref().to_string(),
+            index: Arc::new(Index::new(root.as_ref())),
+            search: RwLock::new(SearchIndex::empty(root.as_ref())),
+            raw_html: false,
         }
     }
 
+    /// Render HTML in pages as-is instead of sanitising it. Anyone who
+    /// can edit a page can then run scripts in its readers' browsers.
+    pub fn set_raw_html(&mut self, raw_html: bool) {
+        self.raw_html = raw_html;
+    }
+
+    /// Watch the wiki directory for changes in a background thread,
+    /// so we only rescan it when something changes. Without this,
+    /// every lookup checks which pages changed on disk.
+    pub fn watch(&self) -> Result<()> {
+        watch::start(self.index.clone())
+    }
+
     /// Is this DB empty?
     pub fn is_empty(&self) -> bool {
         self.len() == 0
@@ -42,20 +94,14 @@ impl DB {
 
     /// How many wiki pages have been created?
     pub fn len(&self) -> usize {
-        if let Ok(res) = shell!("ls -R -1 {} | grep '\\.md' | wc -l", self.root) {
-            res.trim().parse::<usize>().unwrap_or(0)
-        } else {
-            0
-        }
+        self.index.entries().map(|e| e.len()).unwrap_or(0)
     }
 
-    /// Find a single wiki page by name.
+    /// Find a single wiki page by name, or by one of its aliases.
     pub fn find(&self, name: &str) -> Option<Page> {
-        let path = self.pathify(name);
-        self.pages()
-            .unwrap_or_else(|_| vec![])
-            .into_iter()
-            .find(|p| p.path() == path)
+        let entries = self.index.entries().ok()?;
+        self.lookup(&entries, name)
+            .map(|e| Page::new(&self.root, &e.path))
     }
 
     /// Check if a wiki page exists by name.
@@ -65,28 +111,130 @@ impl DB {
 
     /// All the wiki pages, in alphabetical order.
     pub fn pages(&self) -> Result<Vec<Page>> {
-        Ok(shell!("find {} -type f -name '*.md' | sort", self.root)?
-            .trim()
-            .split('\n')
-            .filter(|line| !line.trim().is_empty())
-            .map(|line| Page::new(&self.root, line.trim().replace("//", "/")))
+        Ok(self
+            .index
+            .entries()?
+            .iter()
+            .map(|e| Page::new(&self.root, &e.path))
             .collect())
     }
 
     /// All the wiki page names, in alphabetical order.
     pub fn names(&self) -> Result<Vec<String>> {
-        let mut names: Vec<_> = self.pages()?.iter().map(|p| p.name().to_string()).collect();
+        let mut names: Vec<_> = self
+            .index
+            .entries()?
+            .iter()
+            .map(|e| e.name.clone())
+            .collect();
         names.sort();
         Ok(names)
     }
 
     /// All the wiki page titles, in alphabetical order.
     pub fn titles(&self) -> Result<Vec<String>> {
-        let mut names: Vec<_> = self.pages()?.iter().map(|p| p.title()).collect();
+        let mut names: Vec<_> = self
+            .index
+            .entries()?
+            .iter()
+            .map(|e| e.title.clone())
+            .collect();
         names.sort();
         Ok(names)
     }
 
+    /// Every alias as it's written in its page's front matter, with
+    /// the name and title of that page, in page order.
+    pub fn alias_titles(&self) -> Result<Vec<(String, String, String)>> {
+        Ok(self
+            .index
+            .entries()?
+            .iter()
+            .flat_map(|e| {
+                e.aliases
+                    .iter()
+                    .map(move |alias| (alias.clone(), e.name.clone(), e.title.clone()))
+            })
+            .collect())
+    }
+
+    /// Every page alias, converted to a page name and lowercased,
+    /// mapped to the name of the page it points to. Aliases that are
+    /// also the name of a real page are left out.
+    pub fn aliases(&self) -> Result<HashMap<String, String>> {
+        let entries = self.index.entries()?;
+        let mut aliases = HashMap::new();
+        for entry in entries.iter() {
+            for alias in &entry.aliases {
+                let alias = Self::title_to_name(alias).to_lowercase();
+                if !entries.iter().any(|e| e.name.to_lowercase() == alias) {
+                    aliases.entry(alias).or_insert_with(|| entry.name.clone());
+                }
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// Everything `markdown::to_html` needs to know to link to other
+    /// pages.
+    pub fn markdown_context(&self) -> Result<markdown::Context> {
+        let aliases = self.aliases()?;
+        let entries = self.index.entries()?;
+        let mut names = entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+        names.sort();
+        Ok(markdown::Context {
+            names,
+            aliases,
+            headings: entries
+                .iter()
+                .map(|e| (e.name.clone(), e.headings.clone()))
+                .collect(),
+            paths: entries
+                .iter()
+                .map(|e| (e.name.clone(), e.path.clone()))
+                .collect(),
+            page: None,
+            raw_html: self.raw_html,
+            cache_dir: Some(self.absolute_path(DIAGRAM_CACHE_DIR)),
+        })
+    }
+
+    /// Names of the wiki pages a page links to with [Wiki Links], in
+    /// the order they appear. Includes pages that don't exist yet.
+    pub fn links(&self, name: &str) -> Result<Vec<String>> {
+        let entries = self.index.entries()?;
+        Ok(self
+            .lookup(&entries, name)
+            .map(|e| e.links.clone())
+            .unwrap_or_default())
+    }
+
+    /// Wiki pages that link to this page with [Wiki Links], by name
+    /// or by alias, in alphabetical order.
+    pub fn backlinks(&self, name: &str) -> Result<Vec<Page>> {
+        let entries = self.index.entries()?;
+        let target = match self.lookup(&entries, name) {
+            Some(entry) => entry,
+            None => return Ok(vec![]),
+        };
+        let names = target
+            .aliases
+            .iter()
+            .map(|a| Self::title_to_name(a))
+            .chain(Some(target.name.clone()))
+            .collect::<Vec<_>>();
+        Ok(entries
+            .iter()
+            .filter(|e| {
+                e.path != target.path
+                    && e.links
+                        .iter()
+                        .any(|l| names.iter().any(|n| n.eq_ignore_ascii_case(l)))
+            })
+            .map(|e| Page::new(&self.root, &e.path))
+            .collect())
+    }
+
     /// Recently modified wiki pages.
     pub fn recent(&self) -> Result<Vec<Page>> {
         if !self.is_git() {
@@ -94,13 +242,13 @@ impl DB {
         }
 
         let out = shell!(
-            r#"git --git-dir={:?} log --pretty=format: --name-only -n 30 | grep "\.md\$""#,
+            "git --git-dir={:?} log --pretty=format: --name-only -n 30",
             self.git_dir()
         )?;
         let mut pages = vec![];
         let mut seen = HashMap::new();
-        for path in out.split("\n") {
-            if seen.get(path).is_some() || path == ".md" || path.is_empty() {
+        for path in out.split('\n') {
+            if seen.contains_key(path) || !path.ends_with(".md") || path == ".md" {
                 // TODO: .md hack
                 continue;
             } else {
@@ -111,61 +259,100 @@ impl DB {
         Ok(pages)
     }
 
-    /// All the tags used, in alphabetical order.
-    pub fn tags(&self) -> Result<Vec<String>> {
-        if !self.is_git() {
-            return Ok(vec![]);
-        }
-
-        let out = match shell!(
-            "grep --exclude-dir {:?} -I -E -h -o -r '#(\\w+)' {} | sort | uniq",
-            self.git_dir(),
-            self.root
-        ) {
-            Err(e) => {
-                eprintln!("EGREP ERROR: {}", e);
-                return Err(e);
-            }
-            Ok(out) => out,
+    /// Every commit that touched a page, newest first. Follows
+    /// renames. Empty if the wiki isn't a git repo.
+    pub fn history(&self, name: &str) -> Result<Vec<Revision>> {
+        let page = match self.find(name) {
+            Some(page) if self.is_git() => page,
+            _ => return Ok(vec![]),
         };
 
+        let out = self.git(&[
+            "log",
+            "--follow",
+            "--name-only",
+            "--date=format:%Y-%m-%d %H:%M",
+            "--format=%x1e%H%x1f%an%x1f%ad%x1f%s",
+            "--",
+            page.path_without_root(),
+        ])?;
+
         Ok(out
-            .split('\n')
-            .filter_map(|s| {
-                if s.is_empty() {
-                    None
-                } else {
-                    Some(s[1..].to_string())
-                }
+            .split('\x1e')
+            .filter_map(|commit| {
+                let mut lines = commit.lines();
+                let mut fields = lines.next()?.split('\x1f');
+                Some(Revision {
+                    id: fields.next()?.to_string(),
+                    author: fields.next()?.to_string(),
+                    date: fields.next()?.to_string(),
+                    message: fields.next().unwrap_or("").to_string(),
+                    path: lines.find(|l| !l.is_empty())?.to_string(),
+                })
             })
-            .collect::<Vec<_>>())
+            .collect())
+    }
+
+    /// A page's content as of an earlier revision. `rev` is a full or
+    /// abbreviated commit hash from `history()`.
+    pub fn revision(&self, name: &str, rev: &str) -> Result<(Revision, String)> {
+        let revision = self
+            .history(name)?
+            .into_iter()
+            .find(|r| rev.len() >= 4 && r.id.starts_with(rev))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No revision {} of {}", rev, name),
+                )
+            })?;
+        let body = self.git(&["show", &format!("{}:{}", revision.id, revision.path)])?;
+        Ok((revision, body))
+    }
+
+    /// All the tags used, and the ones nested tags are under, in
+    /// alphabetical order.
+    pub fn tags(&self) -> Result<Vec<String>> {
+        Ok(self.tag_tree()?.into_iter().map(|t| t.name).collect())
+    }
+
+    /// All the tags used and how many pages use them, nested tags
+    /// right after their parent.
+    pub fn tag_tree(&self) -> Result<Vec<tags::Tag>> {
+        let entries = self.index.entries()?;
+        Ok(tags::tree(entries.iter().map(|e| e.tags.as_slice())))
     }
 
     // Don't include the '#' when you search, eg pass in "hashtag" to
-    // search for #hashtag.
+    // search for #hashtag. Pages with tags nested under it, like
+    // #hashtag/nested, are found too.
     pub fn find_pages_with_tag(&self, tag: &str) -> Result<Vec<Page>> {
-        let tag = if tag.starts_with('#') {
-            tag.to_string()
-        } else {
-            format!("#{}", tag)
-        };
+        let tag = tag.trim_start_matches('#');
+        Ok(self
+            .index
+            .entries()?
+            .iter()
+            .filter(|e| e.tags.iter().any(|t| tags::is_under(t, tag)))
+            .map(|e| Page::new(&self.root, &e.path))
+            .collect())
+    }
 
-        let out = shell!(
-            "grep --exclude-dir {:?} -l -r '{}' {}",
-            self.git_dir(),
-            tag,
-            self.root
-        )?;
-        Ok(out
-            .split("\n")
-            .filter_map(|line| {
-                if !line.is_empty() {
-                    Some(Page::new(&self.root, line.split(':').next().unwrap_or("?")))
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>())
+    /// Full-text search of page titles and bodies for words and
+    /// "quoted phrases". Best matches first.
+    pub fn search(&self, query: &str) -> Result<Vec<Hit>> {
+        let entries = self.index.entries()?;
+        let version = self.index.version();
+        {
+            let search = self.search.read().unwrap_or_else(|e| e.into_inner());
+            if search.version() == version {
+                return Ok(search.search(query));
+            }
+        }
+
+        let search = SearchIndex::build(&self.root, version, &entries);
+        let hits = search.search(query);
+        *self.search.write().unwrap_or_else(|e| e.into_inner()) = search;
+        Ok(hits)
     }
 
     /// Create a new wiki page on disk. Name should be the title, such
@@ -181,32 +368,289 @@ impl DB {
         // mkdir -p
         if path.contains('/') {
             if let Some(dir) = Path::new(&path).parent() {
-                fs::create_dir_all(&dir.display().to_string())?;
+                fs::create_dir_all(dir.display().to_string())?;
             }
         }
         let mut file = File::create(&path)?;
         write!(file, "{}", body)?;
+        self.index.refresh(&path);
         Ok(Page::new(&self.root, path))
     }
 
-    /// Save a page to disk. Doesn't track renames, just content
-    /// changes for now.
+    /// Save a page's content to disk. Use `rename` to move it.
     pub fn update(&self, name: &str, body: &str) -> Result<Page> {
-        if !self.exists(name) {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Doesn't exist: {}", name),
-            ));
-        }
-        let path = self.pathify(name);
+        let path = match self.find(name) {
+            Some(page) => page.path().to_string(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Doesn't exist: {}", name),
+                ))
+            }
+        };
         // "atomic" save: write to new file then move to old file
         let tmp = format!("{}~", path);
         let mut file = File::create(&tmp)?;
         file.write_all(body.as_bytes())?;
         fs::rename(tmp, &path)?;
+        self.index.refresh(&path);
         Ok(Page::new(&self.root, path))
     }
 
+    /// Rename or move a page, creating directories as needed, and
+    /// point every [Wiki Link] to it at its new name. Uses `git mv`
+    /// if the wiki is a git repo.
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<Page> {
+        let page = match self.find(name) {
+            Some(page) => page,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Doesn't exist: {}", name),
+                ))
+            }
+        };
+        let path = self.pathify(new_name);
+        if self.exists(new_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Already Exists: {}", path),
+            ));
+        }
+
+        // mkdir -p
+        if let Some(dir) = Path::new(&path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let new_page = Page::new(&self.root, &path);
+        let moved = self.is_git()
+            && self
+                .git(&["mv", page.path_without_root(), new_page.path_without_root()])
+                .is_ok();
+        if !moved {
+            fs::rename(page.path(), &path)?;
+        }
+        self.index.refresh(page.path());
+        self.index.refresh(&path);
+
+        // rewrite [Old Name] links, including any to itself
+        let linked = self
+            .index
+            .entries()?
+            .iter()
+            .filter(|e| e.links.iter().any(|l| l.eq_ignore_ascii_case(page.name())))
+            .map(|e| e.path.clone())
+            .collect::<Vec<_>>();
+        for linked in linked {
+            let linked = Page::new(&self.root, linked);
+            let body = markdown::rewrite_wiki_links(
+                &linked.body(),
+                page.name(),
+                &new_page.title_from_name(),
+            );
+            self.update(linked.path(), &body)?;
+        }
+
+        Ok(new_page)
+    }
+
+    /// Save a file uploaded for a page into `<page>_files/`, next to
+    /// the page. Never overwrites: if the name is taken, a number is
+    /// added to it. Returns the URL of the saved file.
+    pub fn attach(&self, name: &str, filename: &str, data: &[u8]) -> Result<String> {
+        if data.len() > upload::MAX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Too big: {} bytes (max {})", data.len(), upload::MAX_SIZE),
+            ));
+        }
+        let page = match self.find(name) {
+            Some(page) => page,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Doesn't exist: {}", name),
+                ))
+            }
+        };
+
+        let dir = format!("{}_files", page.name());
+        fs::create_dir_all(self.absolute_path(&dir))?;
+        let filename = upload::safe_filename(filename);
+        // photo.png, then photo-1.png, keeping the extension
+        let (stem, ext) = match filename.rsplit_once('.') {
+            Some((stem, ext)) => (stem, format!(".{}", ext)),
+            None => (filename.as_str(), String::new()),
+        };
+        let mut url = format!("/{}/{}", dir, filename);
+        for i in 1.. {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.absolute_path(&url))
+            {
+                Ok(mut file) => {
+                    file.write_all(data)?;
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    url = format!("/{}/{}-{}{}", dir, stem, i, ext);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(url)
+    }
+
+    /// Move a page to the trash. It can be restored later.
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let page = match self.find(name) {
+            Some(page) => page,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Doesn't exist: {}", name),
+                ))
+            }
+        };
+        let trashed = self.new_trash_path(page.name());
+        if let Some(dir) = Path::new(&trashed).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::rename(page.path(), trashed)?;
+        self.index.refresh(page.path());
+        Ok(())
+    }
+
+    /// Pages in the trash, in alphabetical order.
+    pub fn trash(&self) -> Result<Vec<Page>> {
+        let root = self.absolute_path(&format!("{}/", TRASH_DIR));
+        let mut paths = vec![];
+        if Path::new(&root).is_dir() {
+            index::walk(Path::new(&root), &mut paths)?;
+        }
+        paths.sort();
+        Ok(paths.into_iter().map(|p| Page::new(&root, p)).collect())
+    }
+
+    /// Move a page out of the trash and back into the wiki. `name` is
+    /// its name in the trash, so the second copy of "old" is "old~2".
+    pub fn restore(&self, name: &str) -> Result<Page> {
+        let trashed = self.trash_path(name)?;
+        let name = untrashed_name(name);
+        let path = self.pathify(name);
+        if self.exists(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Already Exists: {}", path),
+            ));
+        }
+        if let Some(dir) = Path::new(&path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::rename(trashed, &path)?;
+        self.index.refresh(&path);
+        Ok(Page::new(&self.root, path))
+    }
+
+    /// Permanently delete a page from the trash.
+    pub fn purge(&self, name: &str) -> Result<()> {
+        fs::remove_file(self.trash_path(name)?)
+    }
+
+    /// Names of all the templates for new pages, in alphabetical order.
+    pub fn templates(&self) -> Result<Vec<String>> {
+        let root = self.absolute_path(&format!("{}/", template::DIR));
+        let mut paths = vec![];
+        if Path::new(&root).is_dir() {
+            index::walk(Path::new(&root), &mut paths)?;
+        }
+        paths.sort();
+        Ok(paths
+            .into_iter()
+            .map(|p| Page::new(&root, p).name().to_string())
+            .collect())
+    }
+
+    /// The source of a template, by name.
+    pub fn template(&self, name: &str) -> Option<String> {
+        let path = format!("{}/{}.md", template::DIR, Self::title_to_name(name));
+        fs::read_to_string(self.absolute_path(&path)).ok()
+    }
+
+    /// Name of the template a new page gets if it doesn't ask for
+    /// one: the closest `default` template to the page's directory.
+    pub fn default_template(&self, name: &str) -> Option<String> {
+        template::defaults_for(name)
+            .into_iter()
+            .find(|t| self.template(t).is_some())
+    }
+
+    /// Days with a journal page, oldest first.
+    pub fn journal(&self) -> Result<Vec<NaiveDate>> {
+        let mut days = self
+            .pages()?
+            .iter()
+            .filter_map(|p| journal::date(p.name()))
+            .collect::<Vec<_>>();
+        days.sort();
+        Ok(days)
+    }
+
+    /// The journal page for a day, created from the journal template
+    /// if it doesn't exist yet.
+    pub fn journal_entry(&self, date: NaiveDate) -> Result<Page> {
+        let name = journal::page_name(date);
+        if let Some(page) = self.find(&name) {
+            return Ok(page);
+        }
+        let now = date.and_time(Local::now().time());
+        let body = match self.default_template(&name).and_then(|t| self.template(&t)) {
+            Some(src) => template::expand(&src, &name, now),
+            None => format!("# {}", date.format("%Y-%m-%d")),
+        };
+        self.create(&name, &body)
+    }
+
+    /// Where a page in the trash is, by its name in the trash. Only
+    /// pages that are really in the trash are found, so names like
+    /// "../x" can't reach outside it.
+    fn trash_path(&self, name: &str) -> Result<String> {
+        let not_found =
+            || io::Error::new(io::ErrorKind::NotFound, format!("Not in trash: {}", name));
+        if name.contains("..") || name.contains('\\') {
+            return Err(not_found());
+        }
+        let name = match name.rsplit_once('~') {
+            Some((page, n)) if n.parse::<usize>().is_ok() => {
+                format!("{}~{}", Self::title_to_name(page), n)
+            }
+            _ => Self::title_to_name(name),
+        };
+        let page = self
+            .trash()?
+            .into_iter()
+            .find(|p| p.name() == name)
+            .ok_or_else(not_found)?;
+
+        let trash = fs::canonicalize(self.absolute_path(TRASH_DIR))?;
+        if !fs::canonicalize(page.path())?.starts_with(trash) {
+            return Err(not_found());
+        }
+        Ok(page.path().to_string())
+    }
+
+    /// Where a page goes when it's deleted: `.trash/<name>.md`, or
+    /// `.trash/<name>~2.md` and so on if it's been deleted before, so
+    /// older copies aren't overwritten.
+    fn new_trash_path(&self, name: &str) -> String {
+        let path = |name: &str| self.absolute_path(&format!("{}/{}.md", TRASH_DIR, name));
+        std::iter::once(path(name))
+            .chain((2..).map(|n| path(&format!("{}~{}", name, n))))
+            .find(|path| !Path::new(path).exists())
+            .unwrap_or_default()
+    }
+
     /// Get an FS path to a file, without changing case or characters.
     pub fn absolute_path(&self, path: &str) -> String {
         let path = if path.ends_with(".html") && !path.starts_with("html/") {
@@ -221,11 +665,47 @@ impl DB {
         )
     }
 
+    /// Find a page's index entry by name, or by one of its aliases.
+    /// Real page names win over aliases.
+    fn lookup<'a>(&self, entries: &'a [Entry], name: &str) -> Option<&'a Entry> {
+        // pages whose names don't survive title_to_name, like
+        // "sub dir/it's here", are found by their exact name
+        if let Some(entry) = entries.iter().find(|e| e.name == name) {
+            return Some(entry);
+        }
+        let path = self.pathify(name);
+        let name = Self::title_to_name(name);
+        entries.iter().find(|e| e.path == path).or_else(|| {
+            entries.iter().find(|e| {
+                e.aliases
+                    .iter()
+                    .any(|a| Self::title_to_name(a).eq_ignore_ascii_case(&name))
+            })
+        })
+    }
+
     /// Is this DB tracked with git?
     pub fn is_git(&self) -> bool {
         self.git_dir().exists()
     }
 
+    /// Run a git command in the wiki's root directory. Returns its
+    /// output, or its error if it fails.
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .current_dir(&self.root)
+            .args(["--git-dir=.git", "--work-tree=."])
+            .args(args)
+            .output()?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        }
+    }
+
     /// Path to wiki's Git directory. Doesn't check if it exists.
     fn git_dir(&self) -> PathBuf {
         Path::new(&format!("{}.git", self.root)).to_path_buf()
@@ -253,6 +733,14 @@ impl DB {
     }
 }
 
+/// The page a copy in the trash was deleted from: "old~2" is "old".
+fn untrashed_name(name: &str) -> &str {
+    match name.rsplit_once('~') {
+        Some((page, n)) if n.parse::<usize>().is_ok() => page,
+        _ => name,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -261,11 +749,11 @@ mod test {
     fn test_len() {
         let db = DB::new("./wiki/");
         assert_eq!(6, db.len());
-        assert_eq!(false, db.is_empty());
+        assert!(!db.is_empty());
 
         let db = DB::new("./src/");
         assert_eq!(0, db.len());
-        assert_eq!(true, db.is_empty());
+        assert!(db.is_empty());
     }
 
     #[test]
@@ -277,12 +765,313 @@ mod test {
         assert_eq!("keyboard_shortcuts", pages[1].name());
         assert_eq!("Keyboard Shortcuts", pages[1].title());
 
-        shell!("mkdir -p ./wiki/empty").unwrap();
+        fs::create_dir_all("./wiki/empty").unwrap();
         let db = DB::new("./wiki/empty");
         let pages = db.pages().unwrap();
         println!("{:?}", pages);
         assert_eq!(0, pages.len());
-        shell!("rm -rf ./wiki/empty").unwrap();
+        fs::remove_dir_all("./wiki/empty").unwrap();
+    }
+
+    #[test]
+    fn test_pages_with_spaces_and_quotes() {
+        let dir = std::env::temp_dir().join("deadwiki it's \"quoted\"");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        fs::write(dir.join("sub dir/my page.md"), "# hi #spaced").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+        let pages = db.pages().unwrap();
+        assert_eq!(1, pages.len());
+        assert_eq!("sub dir/my page", pages[0].name());
+        assert_eq!(1, db.find_pages_with_tag("spaced").unwrap().len());
+
+        fs::write(dir.join("sub dir/it's \"here\".md"), "# quoted").unwrap();
+        let page = db.find("sub dir/it's \"here\"").unwrap();
+        assert_eq!("sub dir/it's \"here\"", page.name());
+        assert_eq!("# quoted", page.body());
+        assert_eq!("# hi #spaced", db.find("sub dir/my page").unwrap().body());
+        db.update("sub dir/it's \"here\"", "# still quoted")
+            .unwrap();
+        assert_eq!(2, db.pages().unwrap().len());
+        assert_eq!(
+            "# still quoted",
+            db.find("sub dir/it's \"here\"").unwrap().body()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_watch() {
+        let dir = std::env::temp_dir().join(format!("deadwiki-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+        db.watch().unwrap();
+        assert!(!db.exists("vim"));
+
+        // vim-style save: write a temp file, then move it into place
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/.vim.md.swp"), "# vim").unwrap();
+        fs::rename(dir.join("sub/.vim.md.swp"), dir.join("sub/vim.md")).unwrap();
+        let mut found = false;
+        for _ in 0..100 {
+            if db.exists("sub/vim") {
+                found = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(found);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let db = DB::new("./wiki/");
+        let links = db.backlinks("snippets").unwrap();
+        assert_eq!(1, links.len());
+        assert_eq!("welcome", links[0].name());
+        assert!(db.backlinks("welcome").unwrap().is_empty());
+        assert!(db.backlinks("nope").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_templates() {
+        let dir = std::env::temp_dir().join("deadwiki-templates");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("templates/journal")).unwrap();
+        fs::write(dir.join("templates/default.md"), "# {{title}}").unwrap();
+        fs::write(dir.join("templates/meeting.md"), "# Meeting").unwrap();
+        fs::write(dir.join("templates/journal/default.md"), "# Day").unwrap();
+        fs::write(dir.join("page.md"), "# Page").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+
+        assert_eq!(1, db.len());
+        assert_eq!(
+            vec!["default", "journal/default", "meeting"],
+            db.templates().unwrap()
+        );
+        assert_eq!(Some("# Meeting".into()), db.template("meeting"));
+        assert_eq!(None, db.template("nope"));
+        assert_eq!(
+            Some("journal/default".into()),
+            db.default_template("journal/today")
+        );
+        assert_eq!(Some("default".into()), db.default_template("work/x"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal() {
+        let dir = std::env::temp_dir().join("deadwiki-journal");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("templates/journal")).unwrap();
+        fs::write(dir.join("templates/journal/default.md"), "# Log {{date}}").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+        let day = NaiveDate::from_ymd_opt(2020, 6, 1).unwrap();
+
+        let page = db.journal_entry(day).unwrap();
+        assert_eq!("journal/2020-06-01", page.name());
+        assert_eq!("# Log 2020-06-01", page.body());
+        fs::write(page.path(), "# Changed").unwrap();
+        assert_eq!("# Changed", db.journal_entry(day).unwrap().body());
+
+        fs::write(dir.join("journal/notes.md"), "# Not a day").unwrap();
+        db.create("journal/2020-05-31", "").unwrap();
+        assert_eq!(vec![day.pred_opt().unwrap(), day], db.journal().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_aliases() {
+        let dir = std::env::temp_dir().join("deadwiki-aliases");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("kubernetes.md"),
+            "---\naliases: [k8s, Kube Stuff, home]\n---\n# Kubernetes",
+        )
+        .unwrap();
+        fs::write(dir.join("home.md"), "See [K8s].").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+
+        assert_eq!("kubernetes", db.find("k8s").unwrap().name());
+        assert_eq!("kubernetes", db.find("kube stuff").unwrap().name());
+        assert_eq!("home", db.find("home").unwrap().name());
+        let aliases = db.aliases().unwrap();
+        assert_eq!(2, aliases.len());
+        assert_eq!("kubernetes", aliases["kube_stuff"]);
+        assert_eq!(
+            (
+                "Kube Stuff".to_string(),
+                "kubernetes".to_string(),
+                "Kubernetes".to_string()
+            ),
+            db.alias_titles().unwrap()[1]
+        );
+        assert_eq!("home", db.backlinks("k8s").unwrap()[0].name());
+
+        db.update("k8s", "# Kubernetes").unwrap();
+        assert!(!dir.join("k8s.md").exists());
+        assert!(db.find("k8s").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename() {
+        let dir = std::env::temp_dir().join(format!("deadwiki-rename-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("solar_power.md"), "# Solar Power").unwrap();
+        fs::write(dir.join("home.md"), "See [Solar Power] and [solar_power].").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+
+        let page = db.rename("solar power", "energy/Sun Stuff").unwrap();
+        assert_eq!("energy/Sun_Stuff", page.name());
+        assert!(!db.exists("solar_power"));
+        assert!(db.exists("energy/Sun_Stuff"));
+        assert_eq!(
+            "See [energy/Sun Stuff] and [energy/Sun Stuff].",
+            db.find("home").unwrap().body()
+        );
+        assert_eq!(1, db.backlinks("energy/Sun_Stuff").unwrap().len());
+        assert!(db.rename("home", "energy/Sun Stuff").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trash() {
+        let dir = std::env::temp_dir().join("deadwiki-trash");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/old.md"), "# Old").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+
+        db.delete("sub/old").unwrap();
+        assert!(!db.exists("sub/old"));
+        assert!(db.is_empty());
+        let trash = db.trash().unwrap();
+        assert_eq!(1, trash.len());
+        assert_eq!("sub/old", trash[0].name());
+
+        db.restore("sub/old").unwrap();
+        assert!(db.exists("sub/old"));
+        assert!(db.trash().unwrap().is_empty());
+
+        db.delete("sub/old").unwrap();
+        db.purge("sub/old").unwrap();
+        assert!(db.trash().unwrap().is_empty());
+        assert!(db.restore("sub/old").is_err());
+
+        // deleting a page again keeps the copy that's already there
+        db.delete("sub/old").unwrap_err();
+        db.create("sub/old", "# One").unwrap();
+        db.delete("sub/old").unwrap();
+        db.create("sub/old", "# Two").unwrap();
+        db.delete("sub/old").unwrap();
+        let trash = db.trash().unwrap();
+        assert_eq!(
+            vec!["sub/old", "sub/old~2"],
+            trash.iter().map(|p| p.name()).collect::<Vec<_>>()
+        );
+        db.restore("sub/old~2").unwrap();
+        assert_eq!("# Two", fs::read_to_string(dir.join("sub/old.md")).unwrap());
+        assert!(db.restore("sub/old").is_err());
+        db.purge("sub/old").unwrap();
+        assert!(db.trash().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trash_outside_wiki() {
+        let base = std::env::temp_dir().join(format!("deadwiki-trash-out-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let dir = base.join("wiki");
+        fs::create_dir_all(dir.join(TRASH_DIR)).unwrap();
+        fs::write(base.join("x.md"), "# Not in the wiki").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+
+        for name in ["../../x", ".../.../x", "..\\..\\x", "/x"] {
+            assert!(db.purge(name).is_err(), "{}", name);
+            assert!(db.restore(name).is_err(), "{}", name);
+        }
+        assert!(base.join("x.md").exists());
+        assert!(db.is_empty());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_attach() {
+        let dir = std::env::temp_dir().join("deadwiki-attach");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/page.md"), "# Page").unwrap();
+        let db = DB::new(format!("{}/", dir.display()));
+
+        let url = db.attach("sub/page", "my cat.png", b"meow").unwrap();
+        assert_eq!("/sub/page_files/my_cat.png", url);
+        assert_eq!(
+            b"meow",
+            fs::read(dir.join("sub/page_files/my_cat.png"))
+                .unwrap()
+                .as_slice()
+        );
+        let url = db.attach("sub/page", "my cat.png", b"purr").unwrap();
+        assert_eq!("/sub/page_files/my_cat-1.png", url);
+        let url = db.attach("sub/page", "my cat.png", b"hiss").unwrap();
+        assert_eq!("/sub/page_files/my_cat-2.png", url);
+        assert!(upload::is_inline(&url));
+        assert_eq!(
+            "![my_cat-2.png](/sub/page_files/my_cat-2.png)",
+            upload::markdown_link("my_cat-2.png", &url)
+        );
+        assert_eq!(1, db.len());
+
+        assert!(db.attach("nope", "a.png", b"").is_err());
+        assert!(db
+            .attach("sub/page", "big.png", &vec![0; upload::MAX_SIZE + 1])
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = std::env::temp_dir().join("deadwiki-history");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .current_dir(&dir)
+                .args(["-c", "user.name=Dee", "-c", "user.email=dee@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q"]);
+        let first = "# Page\n\nThe first version.\nIt has a few lines.\nSo git can follow it.\n";
+        fs::write(dir.join("old.md"), first).unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-qm", "add page"]);
+        git(&["mv", "old.md", "page.md"]);
+        fs::write(dir.join("page.md"), first.replace("first", "second")).unwrap();
+        git(&["commit", "-qam", "rename page"]);
+
+        let db = DB::new(format!("{}/", dir.display()));
+        let history = db.history("page").unwrap();
+        assert_eq!(2, history.len());
+        assert_eq!("rename page", history[0].message);
+        assert_eq!("Dee", history[1].author);
+        assert_eq!("old.md", history[1].path);
+
+        let (rev, body) = db.revision("page", history[1].short_id()).unwrap();
+        assert_eq!("add page", rev.message);
+        assert_eq!(first, body);
+        assert!(db.revision("page", "--help").is_err());
+
+        db.update("page", &body).unwrap();
+        assert_eq!(first, db.find("page").unwrap().body());
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
@@ -314,6 +1103,17 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_tags() {
+        // not a git repo, and `#{name}` in a code block isn't a tag
+        let db = DB::new("./wiki/");
+        assert_eq!(
+            vec!["fish", "markdown", "shell", "snippet"],
+            db.tags().unwrap()
+        );
+        assert_eq!(2, db.find_pages_with_tag("#markdown").unwrap().len());
+    }
+
     #[test]
     fn test_is_git() {
         let db = DB::new("./wiki/");
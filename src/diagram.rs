@@ -0,0 +1,179 @@
+//! Graphviz diagrams: fenced ` ```dot ` blocks are laid out in Rust,
+//! with `layout-rs`, so there's nothing to install.
+//!
+//! Diagrams go in the page as an `<img>` with a `data:` URL, not as
+//! inline `<svg>`: images can't run scripts, so the sanitiser doesn't
+//! have to know anything about SVG, and the SVG's styles can't leak
+//! into the page.
+//!
+//! Each diagram is cached as an SVG file named after the SHA-256 of
+//! its source, so pages with big graphs don't get laid out on every
+//! view. The least recently used ones are thrown out when there are
+//! more than `MAX_CACHED`.
+
+use {
+    crate::utils::html_encode,
+    layout::{backends::svg::SVGWriter, gv},
+    sha2::{Digest, Sha256},
+    std::{fs, panic, path::Path, time::SystemTime},
+};
+
+/// How many diagrams we keep in the cache.
+const MAX_CACHED: usize = 500;
+
+/// Languages, after the opening fence, that we draw.
+pub fn supports(lang: &str) -> bool {
+    lang == "dot" || lang == "graphviz"
+}
+
+/// Lay out a graph in the DOT language and draw it as SVG.
+pub fn to_svg(dot: &str) -> Result<String, String> {
+    let graph = gv::DotParser::new(dot)
+        .process()
+        .map_err(|e| format!("Can't parse graph: {}", e))?;
+    // layout-rs panics on some graphs it can't handle, like ones with
+    // nothing in them. That shouldn't take the page down with it.
+    panic::catch_unwind(|| {
+        let mut builder = gv::GraphBuilder::new();
+        builder.visit_graph(&graph);
+        let mut svg = SVGWriter::new();
+        builder.get().do_it(false, false, false, &mut svg);
+        svg.finalize()
+    })
+    .map_err(|_| "Can't lay out graph.".to_string())
+}
+
+/// A diagram as HTML: an `<img>` with the SVG inline, as a `data:`
+/// URL, or the error if it can't be drawn. SVGs are cached in
+/// `cache_dir`, if there is one.
+pub fn to_html(dot: &str, cache_dir: Option<&str>) -> String {
+    let svg = match cache_dir {
+        Some(dir) => cached_svg(dot, Path::new(dir)),
+        None => to_svg(dot),
+    };
+    match svg {
+        Ok(svg) => format!(
+            "<div class=\"diagram\"><img src=\"data:image/svg+xml;base64,{}\" alt=\"diagram\"></div>\n",
+            base64::encode(svg)
+        ),
+        Err(e) => format!("<pre class=\"diagram-error\">{}</pre>\n", html_encode(&e)),
+    }
+}
+
+/// `to_svg`, but read from `dir` if we've drawn this diagram before.
+/// Errors aren't cached.
+fn cached_svg(dot: &str, dir: &Path) -> Result<String, String> {
+    let path = dir.join(format!("{:x}.svg", Sha256::digest(dot.as_bytes())));
+    if let Ok(svg) = fs::read_to_string(&path) {
+        // mark it as used, so it's pruned last
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        return Ok(svg);
+    }
+
+    let svg = to_svg(dot)?;
+    // the cache is only a cache, so failing to write it is fine
+    if fs::create_dir_all(dir).is_ok() {
+        // keep it out of git wikis
+        let _ = fs::write(dir.join(".gitignore"), "*\n");
+        if fs::write(&path, &svg).is_ok() {
+            prune(dir, MAX_CACHED);
+        }
+    }
+    Ok(svg)
+}
+
+/// Delete the least recently used SVGs in `dir`, so there are no more
+/// than `keep` of them.
+fn prune(dir: &Path, keep: usize) {
+    let mut cached = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".svg"))
+            .map(|e| {
+                let used = e.metadata().and_then(|m| m.modified());
+                (used.unwrap_or(SystemTime::UNIX_EPOCH), e.path())
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+    if cached.len() <= keep {
+        return;
+    }
+    cached.sort();
+    for (_, path) in &cached[..cached.len() - keep] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_svg() {
+        let svg = to_svg("digraph { a -> \"b<c>\" }").unwrap();
+        assert!(svg.contains("<svg "));
+        assert!(svg.contains("b&lt;c&gt;"));
+        assert!(to_svg("digraph { a -> ; }")
+            .unwrap_err()
+            .starts_with("Can't parse graph"));
+    }
+
+    #[test]
+    fn test_to_html() {
+        let dir = std::env::temp_dir().join("deadwiki-diagrams");
+        let _ = fs::remove_dir_all(&dir);
+        let dir_str = dir.to_str().unwrap();
+
+        let html = to_html("graph { a -- b }", Some(dir_str));
+        assert!(html.starts_with("<div class=\"diagram\"><img src=\"data:image/svg+xml;base64,"));
+        let cached = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".svg"))
+            .collect::<Vec<_>>();
+        assert_eq!(1, cached.len());
+        assert!(dir.join(".gitignore").exists());
+
+        // drawn from the cache next time
+        fs::write(dir.join(&cached[0]), "<svg>cached</svg>").unwrap();
+        assert_eq!(
+            format!(
+                "<div class=\"diagram\"><img src=\"data:image/svg+xml;base64,{}\" alt=\"diagram\"></div>\n",
+                base64::encode("<svg>cached</svg>")
+            ),
+            to_html("graph { a -- b }", Some(dir_str))
+        );
+
+        assert_eq!(
+            "<pre class=\"diagram-error\">Can&#x27;t parse graph: port</pre>\n",
+            to_html("graph { a -- ; }", Some(dir_str))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = std::env::temp_dir().join("deadwiki-diagrams-prune");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["old.svg", "new.svg", "newer.svg"].iter().enumerate() {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(60 * (3 - i as u64)))
+                .unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "*\n").unwrap();
+
+        prune(&dir, 3);
+        assert!(dir.join("old.svg").exists());
+        prune(&dir, 2);
+        assert!(!dir.join("old.svg").exists());
+        assert!(dir.join("new.svg").exists());
+        assert!(dir.join("newer.svg").exists());
+        assert!(dir.join(".gitignore").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+//! Render the differences between two versions of a wiki page as an
+//! HTML table, either inline (one column) or side-by-side.
+
+use {
+    crate::utils::html_encode,
+    similar::{ChangeTag, TextDiff},
+};
+
+/// Lines of unchanged context to show around each change.
+const CONTEXT: usize = 3;
+
+/// Show changed lines, or highlight the changed words in them too?
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Line,
+    Word,
+}
+
+/// One column, or old and new next to each other?
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Inline,
+    SideBySide,
+}
+
+/// A line from the old or new version, split into parts that are
+/// highlighted as changed or not.
+struct Line {
+    num: usize,
+    parts: Vec<(bool, String)>,
+}
+
+/// A chunk of the diff.
+enum Row {
+    Same(Line, Line),
+    Changed(Vec<Line>, Vec<Line>),
+    /// Unchanged lines we skipped.
+    Gap,
+}
+
+/// HTML table showing what changed between `old` and `new`.
+pub fn to_html(old: &str, new: &str, granularity: Granularity, layout: Layout) -> String {
+    let rows = rows(old, new, granularity);
+    let class = match layout {
+        Layout::Inline => "inline",
+        Layout::SideBySide => "side-by-side",
+    };
+
+    let mut out = format!("<table class=\"diff {}\">\n", class);
+    if rows.is_empty() {
+        out.push_str("<tr class=\"gap\"><td>No changes.</td></tr>\n");
+    }
+    for row in rows {
+        match (row, layout) {
+            (Row::Gap, Layout::Inline) => {
+                out.push_str("<tr class=\"gap\"><td colspan=\"3\">…</td></tr>\n")
+            }
+            (Row::Gap, Layout::SideBySide) => {
+                out.push_str("<tr class=\"gap\"><td colspan=\"4\">…</td></tr>\n")
+            }
+            (Row::Same(old, new), Layout::Inline) => {
+                out.push_str("<tr>");
+                push_num(&mut out, Some(&old));
+                push_num(&mut out, Some(&new));
+                push_line(&mut out, "", Some(&new));
+                out.push_str("</tr>\n");
+            }
+            (Row::Same(old, new), Layout::SideBySide) => {
+                out.push_str("<tr>");
+                push_num(&mut out, Some(&old));
+                push_line(&mut out, "", Some(&old));
+                push_num(&mut out, Some(&new));
+                push_line(&mut out, "", Some(&new));
+                out.push_str("</tr>\n");
+            }
+            (Row::Changed(dels, ins), Layout::Inline) => {
+                for line in &dels {
+                    out.push_str("<tr class=\"del\">");
+                    push_num(&mut out, Some(line));
+                    push_num(&mut out, None);
+                    push_line(&mut out, "del", Some(line));
+                    out.push_str("</tr>\n");
+                }
+                for line in &ins {
+                    out.push_str("<tr class=\"ins\">");
+                    push_num(&mut out, None);
+                    push_num(&mut out, Some(line));
+                    push_line(&mut out, "ins", Some(line));
+                    out.push_str("</tr>\n");
+                }
+            }
+            (Row::Changed(dels, ins), Layout::SideBySide) => {
+                for i in 0..dels.len().max(ins.len()) {
+                    let (old, new) = (dels.get(i), ins.get(i));
+                    out.push_str("<tr>");
+                    push_num(&mut out, old);
+                    push_line(&mut out, if old.is_some() { "del" } else { "" }, old);
+                    push_num(&mut out, new);
+                    push_line(&mut out, if new.is_some() { "ins" } else { "" }, new);
+                    out.push_str("</tr>\n");
+                }
+            }
+        }
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+/// Diff two texts line by line, keeping a little context around each
+/// change.
+fn rows(old: &str, new: &str, granularity: Granularity) -> Vec<Row> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut rows = vec![];
+
+    for (i, group) in diff.grouped_ops(CONTEXT).iter().enumerate() {
+        if i > 0 {
+            rows.push(Row::Gap);
+        }
+
+        let (mut dels, mut ins) = (vec![], vec![]);
+        for op in group {
+            for change in diff.iter_inline_changes(op) {
+                let mut parts = change
+                    .iter_strings_lossy()
+                    .map(|(changed, s)| (changed, s.trim_end_matches('\n').to_string()))
+                    .collect::<Vec<_>>();
+                if granularity == Granularity::Line {
+                    let text = parts.into_iter().map(|(_, s)| s).collect();
+                    parts = vec![(false, text)];
+                }
+
+                match change.tag() {
+                    ChangeTag::Delete => dels.push(Line {
+                        num: change.old_index().unwrap_or(0) + 1,
+                        parts,
+                    }),
+                    ChangeTag::Insert => ins.push(Line {
+                        num: change.new_index().unwrap_or(0) + 1,
+                        parts,
+                    }),
+                    ChangeTag::Equal => {
+                        if !dels.is_empty() || !ins.is_empty() {
+                            rows.push(Row::Changed(dels, ins));
+                            dels = vec![];
+                            ins = vec![];
+                        }
+                        rows.push(Row::Same(
+                            Line {
+                                num: change.old_index().unwrap_or(0) + 1,
+                                parts: parts.clone(),
+                            },
+                            Line {
+                                num: change.new_index().unwrap_or(0) + 1,
+                                parts,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        if !dels.is_empty() || !ins.is_empty() {
+            rows.push(Row::Changed(dels, ins));
+        }
+    }
+
+    rows
+}
+
+/// Line number cell.
+fn push_num(out: &mut String, line: Option<&Line>) {
+    match line {
+        Some(line) => out.push_str(&format!("<td class=\"num\">{}</td>", line.num)),
+        None => out.push_str("<td class=\"num\"></td>"),
+    }
+}
+
+/// Line content cell, with changed words wrapped in <ins> or <del>.
+fn push_line(out: &mut String, class: &str, line: Option<&Line>) {
+    if class.is_empty() {
+        out.push_str("<td class=\"line\">");
+    } else {
+        out.push_str(&format!("<td class=\"line {}\">", class));
+    }
+    for (changed, text) in line.map(|l| l.parts.as_slice()).unwrap_or_default() {
+        if *changed && !class.is_empty() {
+            out.push_str(&format!("<{}>{}</{}>", class, html_encode(text), class));
+        } else {
+            out.push_str(&html_encode(text));
+        }
+    }
+    out.push_str("</td>");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inline_lines() {
+        let html = to_html(
+            "one\ntwo\nthree\n",
+            "one\n2\nthree\n",
+            Granularity::Line,
+            Layout::Inline,
+        );
+        assert!(html.contains(r#"<tr class="del"><td class="num">2</td><td class="num"></td><td class="line del">two</td></tr>"#));
+        assert!(html.contains(r#"<tr class="ins"><td class="num"></td><td class="num">2</td><td class="line ins">2</td></tr>"#));
+        assert!(html.contains(r#"<td class="line">one</td>"#));
+    }
+
+    #[test]
+    fn test_side_by_side_words() {
+        let html = to_html(
+            "the quick <fox>\n",
+            "the slow <fox>\n",
+            Granularity::Word,
+            Layout::SideBySide,
+        );
+        assert!(html.contains(r#"<td class="line del">the <del>quick</del> &lt;fox&gt;</td>"#));
+        assert!(html.contains(r#"<td class="line ins">the <ins>slow</ins> &lt;fox&gt;</td>"#));
+    }
+
+    #[test]
+    fn test_context() {
+        let old = (1..=20).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = (1..=20)
+            .map(|i| match i {
+                2 => "two\n".to_string(),
+                19 => "nineteen\n".to_string(),
+                _ => format!("{}\n", i),
+            })
+            .collect::<String>();
+        let html = to_html(&old, &new, Granularity::Line, Layout::Inline);
+        assert!(html.contains(r#"<tr class="gap">"#));
+        assert!(!html.contains(r#">10</td>"#));
+        assert!(to_html("same", "same", Granularity::Line, Layout::Inline).contains("No changes."));
+    }
+}
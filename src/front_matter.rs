@@ -0,0 +1,214 @@
+//! Optional metadata at the top of a wiki page, in YAML:
+//!
+//! ```text
+//! ---
+//! title: iOS Notes
+//! tags: [apple, phones]
+//! ---
+//! ```
+//!
+//! Or TOML, between `+++` lines. `title`, `tags`, `aliases` and
+//! `created` mean something to deadwiki. Any other keys are passed
+//! through to templates.
+
+use {
+    serde_yaml::{Mapping, Value},
+    std::ops::Range,
+};
+
+/// Parsed front matter. Empty if the page doesn't have any.
+#[derive(Debug, Clone, Default)]
+pub struct FrontMatter {
+    fields: Mapping,
+}
+
+impl FrontMatter {
+    /// Parse the front matter at the top of a page, if there is any.
+    /// Blocks that aren't valid YAML or TOML maps are left alone and
+    /// treated as part of the body.
+    pub fn parse(md: &str) -> FrontMatter {
+        FrontMatter {
+            fields: span(md).map(|(_, fields)| fields).unwrap_or_default(),
+        }
+    }
+
+    /// Any value, by key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields.get(&Value::String(key.into()))
+    }
+
+    /// All the keys, in the order they appear.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().filter_map(|(k, _)| k.as_str())
+    }
+
+    /// Everything, as a YAML map.
+    pub fn fields(&self) -> &Mapping {
+        &self.fields
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.string("title").filter(|t| !t.trim().is_empty())
+    }
+
+    /// When the page was created, as written.
+    pub fn created(&self) -> Option<String> {
+        self.string("created")
+    }
+
+    /// Tags, without any '#'.
+    pub fn tags(&self) -> Vec<String> {
+        self.list("tags")
+            .into_iter()
+            .map(|t| t.trim_start_matches('#').to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+
+    /// Other names for this page.
+    pub fn aliases(&self) -> Vec<String> {
+        self.list("aliases")
+    }
+
+    /// A scalar value as a string.
+    fn string(&self, key: &str) -> Option<String> {
+        match self.get(key)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    /// A list of strings, or a single comma separated string.
+    fn list(&self, key: &str) -> Vec<String> {
+        match self.get(key) {
+            Some(Value::Sequence(items)) => items
+                .iter()
+                .filter_map(|v| match v {
+                    Value::String(s) => Some(s.trim().to_string()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            Some(Value::String(s)) => s
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// The page's Markdown, without its front matter.
+pub fn strip(md: &str) -> &str {
+    match span(md) {
+        Some((range, _)) => &md[range.end..],
+        None => md,
+    }
+}
+
+/// Where the front matter block is, delimiters and all, and what's in
+/// it.
+pub fn span(md: &str) -> Option<(Range<usize>, Mapping)> {
+    let delimiter = match md.lines().next()?.trim_end() {
+        "---" => "---",
+        "+++" => "+++",
+        _ => return None,
+    };
+
+    let start = md.find('\n')? + 1;
+    let mut pos = start;
+    for line in md[start..].split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == delimiter || (delimiter == "---" && trimmed == "...") {
+            let fields = parse_fields(delimiter, &md[start..pos])?;
+            return Some((0..pos + line.len(), fields));
+        }
+        pos += line.len();
+    }
+    None
+}
+
+/// Parse the inside of a front matter block into a map.
+fn parse_fields(delimiter: &str, src: &str) -> Option<Mapping> {
+    let value = if delimiter == "+++" {
+        toml_to_yaml(toml::from_str(src).ok()?)
+    } else if src.trim().is_empty() {
+        return Some(Mapping::new());
+    } else {
+        serde_yaml::from_str(src).ok()?
+    };
+    match value {
+        Value::Mapping(map) => Some(map),
+        _ => None,
+    }
+}
+
+/// Everything is stored as YAML values. TOML dates become strings.
+fn toml_to_yaml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::Number(i.into()),
+        toml::Value::Float(f) => Value::Number(f.into()),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(items) => Value::Sequence(items.into_iter().map(toml_to_yaml).collect()),
+        toml::Value::Table(table) => Value::Mapping(
+            table
+                .into_iter()
+                .map(|(k, v)| (Value::String(k), toml_to_yaml(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_yaml() {
+        let md = "---\ntitle: iOS Notes\ntags: [apple, '#phones']\naliases: iPhone, iPad\ncreated: 2020-06-01\nmood: ok\n---\n# Hi\n";
+        let fm = FrontMatter::parse(md);
+        assert_eq!(Some("iOS Notes".into()), fm.title());
+        assert_eq!(vec!["apple", "phones"], fm.tags());
+        assert_eq!(vec!["iPhone", "iPad"], fm.aliases());
+        assert_eq!(Some("2020-06-01".into()), fm.created());
+        assert_eq!(Some("ok"), fm.get("mood").and_then(|v| v.as_str()));
+        assert_eq!(
+            vec!["title", "tags", "aliases", "created", "mood"],
+            fm.keys().collect::<Vec<_>>()
+        );
+        assert_eq!("# Hi\n", strip(md));
+    }
+
+    #[test]
+    fn test_toml() {
+        let md = "+++\ntitle = \"iOS Notes\"\ntags = [\"apple\"]\ncreated = 2020-06-01\n+++\nBody";
+        let fm = FrontMatter::parse(md);
+        assert_eq!(Some("iOS Notes".into()), fm.title());
+        assert_eq!(vec!["apple"], fm.tags());
+        assert_eq!(Some("2020-06-01".into()), fm.created());
+        assert_eq!("Body", strip(md));
+    }
+
+    #[test]
+    fn test_no_front_matter() {
+        for md in &[
+            "# Just a page\n",
+            "---\nnot closed\n",
+            "---\n- a list\n---\n",
+            "Text\n---\ntitle: no\n---\n",
+        ] {
+            assert!(FrontMatter::parse(md).is_empty());
+            assert_eq!(*md, strip(md));
+        }
+        assert_eq!("Body", strip("---\n---\nBody"));
+    }
+}
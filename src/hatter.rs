@@ -2,7 +2,6 @@
 
 use {
     crate::utils::html_encode,
-    hatter,
     std::{io, ops},
     vial::asset,
 };
@@ -24,6 +23,12 @@ impl ops::DerefMut for Hatter {
     }
 }
 
+impl Default for Hatter {
+    fn default() -> Hatter {
+        Hatter::new()
+    }
+}
+
 impl Hatter {
     pub fn new() -> Hatter {
         Hatter {
@@ -37,7 +42,7 @@ impl Hatter {
 
         let src = asset::to_string(path)?;
         match self.env.render(&src) {
-            Ok(out) => Ok(out.into()),
+            Ok(out) => Ok(out),
             Err(err) => match err.kind {
                 ParseError | SyntaxError | RuntimeError => {
                     let (errline, errcol) = hatter::line_and_col(&src, err.pos);
@@ -0,0 +1,88 @@
+//! Syntax highlighting for fenced code blocks, done on the server so
+//! it works without JavaScript.
+//!
+//! Code is wrapped in `<span>`s with CSS classes, like
+//! `hl-keyword`, which `assets/css/syntax.css` colours for both
+//! light and dark mode.
+
+use {
+    std::sync::OnceLock,
+    syntect::{
+        html::{ClassStyle, ClassedHTMLGenerator},
+        parsing::{SyntaxReference, SyntaxSet},
+        util::LinesWithEndings,
+    },
+};
+
+/// Prefix for all our CSS classes, so they don't clash with the
+/// page's.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Loading the syntax definitions is slow, so it's only done once.
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    let lang = lang.trim();
+    if lang.is_empty() {
+        None
+    } else {
+        syntaxes().find_syntax_by_token(lang)
+    }
+}
+
+/// The language of a fenced code block from its info string:
+/// "rust,ignore" and "rust title=x" are both "rust".
+pub fn language(info: &str) -> &str {
+    info.split(|c: char| c.is_whitespace() || c == ',' || c == '{')
+        .next()
+        .unwrap_or("")
+}
+
+/// Do we know how to highlight this language?
+pub fn supports(lang: &str) -> bool {
+    syntax(lang).is_some()
+}
+
+/// Highlight some code as HTML, if we know the language. Doesn't
+/// include the `<pre>`.
+pub fn to_html(code: &str, lang: &str) -> Option<String> {
+    let syntax = syntax(lang)?;
+    let mut html = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes(), CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        html.parse_html_for_line_which_includes_newline(line).ok()?;
+    }
+    Some(html.finalize())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_language() {
+        assert_eq!("rust", language("rust"));
+        assert_eq!("rust", language("rust,ignore"));
+        assert_eq!("sh", language("sh title=x"));
+        assert_eq!("", language(""));
+    }
+
+    #[test]
+    fn test_to_html() {
+        assert!(supports("rust") && supports("RS") && supports("python"));
+        assert!(!supports("nope") && !supports(""));
+
+        let html = to_html("fn main() {}\n", "rust").unwrap();
+        assert!(html.contains(r#"<span class="hl-storage hl-type hl-function hl-rust">fn</span>"#));
+        assert!(html.contains("main"));
+
+        let html = to_html("if a < b:\n  c = \"&\"\n", "py").unwrap();
+        assert!(html.contains("&lt;") && html.contains("&amp;"));
+        assert!(!html.contains("a < b"));
+
+        assert_eq!(None, to_html("x", "nope"));
+    }
+}
@@ -0,0 +1,284 @@
+//! In-memory index of every wiki page, so we don't have to walk the
+//! wiki directory on every lookup. Kept fresh by `watch`, or without
+//! it by re-reading only the pages that changed since the last read.
+
+use {
+    crate::{markdown, template, Page},
+    std::{
+        collections::HashMap,
+        fs, io, mem,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            RwLock, RwLockReadGuard,
+        },
+        time::SystemTime,
+    },
+};
+
+/// What we know about a single wiki page.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub name: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub links: Vec<String>,
+    /// Other names for the page, as written in its front matter.
+    pub aliases: Vec<String>,
+    /// Ids of the page's headings.
+    pub headings: Vec<String>,
+    /// When the file was last modified, and its size, when we read
+    /// it. If either changes, it needs reading again.
+    pub(crate) stamp: Option<(SystemTime, u64)>,
+}
+
+impl Entry {
+    /// Read a page from disk and index it.
+    fn load(root: &str, path: &str) -> Entry {
+        // before reading, so a change while we read is seen next time
+        let stamp = stamp(path);
+        let page = Page::new(root, path);
+        let body = page.body();
+        let front_matter = page.front_matter();
+        let mut tags = front_matter.tags();
+        for tag in markdown::tags(&body) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        Entry {
+            path: path.to_string(),
+            name: page.name().to_string(),
+            title: page.title(),
+            tags,
+            links: markdown::wiki_links(&body),
+            aliases: front_matter.aliases(),
+            headings: markdown::toc(&body).into_iter().map(|h| h.id).collect(),
+            stamp,
+        }
+    }
+}
+
+/// A file's modification time and size, if we can get them.
+fn stamp(path: &str) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+pub struct Index {
+    root: String,
+    entries: RwLock<Vec<Entry>>,
+    /// Do the entries need to be rebuilt from scratch?
+    stale: AtomicBool,
+    /// Is someone telling us when files change? If not, we check for
+    /// changes on every read.
+    watched: AtomicBool,
+    /// Bumped every time the entries change.
+    version: AtomicUsize,
+}
+
+impl Index {
+    pub fn new(root: &str) -> Index {
+        Index {
+            root: root.to_string(),
+            entries: RwLock::new(vec![]),
+            stale: AtomicBool::new(true),
+            watched: AtomicBool::new(false),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wiki root this index was built from.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Changes every time the entries do, so anything built from them
+    /// knows when it's out of date.
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// All the entries, in alphabetical order by path. Rebuilds the
+    /// index first if it's out of date, or catches up with changes on
+    /// disk if nothing is watching for them.
+    pub fn entries(&self) -> io::Result<RwLockReadGuard<'_, Vec<Entry>>> {
+        if self.stale.load(Ordering::SeqCst) {
+            self.rebuild()?;
+        } else if !self.watched.load(Ordering::SeqCst) {
+            self.sync()?;
+        }
+        Ok(self.entries.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Walk the wiki directory and re-read every page.
+    pub fn rebuild(&self) -> io::Result<()> {
+        let paths = self.paths()?;
+        let entries = paths
+            .iter()
+            .map(|path| Entry::load(&self.root, path))
+            .collect();
+        *self.entries.write().unwrap_or_else(|e| e.into_inner()) = entries;
+        self.stale.store(false, Ordering::SeqCst);
+        self.version.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Walk the wiki directory, but only re-read pages that are new
+    /// or whose modification time or size changed since we read them.
+    /// Pages that are gone are dropped.
+    pub fn sync(&self) -> io::Result<()> {
+        let paths = self.paths()?;
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let mut old = mem::take(&mut *entries)
+            .into_iter()
+            .map(|e| (e.path.clone(), e))
+            .collect::<HashMap<_, _>>();
+        let mut changed = paths.len() != old.len();
+        *entries = paths
+            .iter()
+            .map(|path| match old.remove(path) {
+                Some(entry) if entry.stamp.is_some() && entry.stamp == stamp(path) => entry,
+                _ => {
+                    changed = true;
+                    Entry::load(&self.root, path)
+                }
+            })
+            .collect();
+        if changed {
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Every page's path, sorted.
+    fn paths(&self) -> io::Result<Vec<String>> {
+        let mut paths = vec![];
+        walk(Path::new(&self.root), &mut paths)?;
+        paths.retain(|path| !self.is_template(path));
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Re-read a single page after it's been created, changed, or
+    /// deleted.
+    pub fn refresh(&self, path: &str) {
+        if self.is_template(path) {
+            return;
+        }
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let idx = entries.binary_search_by(|e| e.path.as_str().cmp(path));
+        let exists = Path::new(path).is_file();
+        match idx {
+            Ok(idx) if exists => entries[idx] = Entry::load(&self.root, path),
+            Ok(idx) => {
+                entries.remove(idx);
+            }
+            Err(idx) if exists => entries.insert(idx, Entry::load(&self.root, path)),
+            Err(_) => return,
+        }
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Templates for new pages live in the wiki, but aren't pages.
+    fn is_template(&self, path: &str) -> bool {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .trim_start_matches('/')
+            .starts_with(&format!("{}/", template::DIR))
+    }
+
+    /// Throw everything out and rebuild on the next read.
+    pub fn invalidate(&self) {
+        self.stale.store(true, Ordering::SeqCst);
+    }
+
+    /// Tell the index whether a watcher is keeping it up to date.
+    pub fn set_watched(&self, watched: bool) {
+        self.watched.store(watched, Ordering::SeqCst);
+    }
+}
+
+/// Recursively collect the path of every `.md` file under `dir`.
+/// Hidden files and directories, like `.git`, are skipped.
+pub fn walk(dir: &Path, paths: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            paths.push(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_refresh() {
+        let dir = std::env::temp_dir().join("deadwiki-index-refresh");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("one.md"), "# one").unwrap();
+        let root = format!("{}/", dir.display());
+        let index = Index::new(&root);
+        index.set_watched(true);
+        assert_eq!(1, index.entries().unwrap().len());
+
+        // not seen until refreshed
+        let two = dir.join("two.md").display().to_string();
+        fs::write(&two, "[One] #two").unwrap();
+        assert_eq!(1, index.entries().unwrap().len());
+        index.refresh(&two);
+        {
+            let entries = index.entries().unwrap();
+            assert_eq!(2, entries.len());
+            assert_eq!("two", entries[1].name);
+            assert_eq!(vec!["two"], entries[1].tags);
+            assert_eq!(vec!["One"], entries[1].links);
+        }
+
+        fs::remove_file(&two).unwrap();
+        index.refresh(&two);
+        assert_eq!(1, index.entries().unwrap().len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = std::env::temp_dir().join(format!("deadwiki-index-sync-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("one.md"), "# one").unwrap();
+        fs::write(dir.join("two.md"), "# two").unwrap();
+        let index = Index::new(&format!("{}/", dir.display()));
+        assert_eq!(2, index.entries().unwrap().len());
+
+        // nothing changed, so nothing is re-read
+        let version = index.version();
+        assert_eq!(2, index.entries().unwrap().len());
+        assert_eq!(version, index.version());
+
+        // changes are seen without a watcher
+        fs::write(dir.join("two.md"), "# two #tagged").unwrap();
+        fs::write(dir.join("three.md"), "# three").unwrap();
+        fs::remove_file(dir.join("one.md")).unwrap();
+        {
+            let entries = index.entries().unwrap();
+            assert_eq!(
+                vec!["three", "two"],
+                entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>()
+            );
+            assert_eq!(vec!["tagged"], entries[1].tags);
+        }
+        assert!(index.version() > version);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
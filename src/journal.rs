@@ -0,0 +1,113 @@
+//! Daily journal pages, one per day: `journal/2020-06-01.md`.
+//!
+//! New entries use the `templates/journal/default.md` template, if
+//! the wiki has one.
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+/// Where journal pages live, inside the wiki root.
+pub const DIR: &str = "journal";
+
+/// Format of a journal page's name, after the directory.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// One cell in a month's calendar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Day {
+    pub date: NaiveDate,
+    /// Is there a journal page for this day?
+    pub entry: bool,
+}
+
+/// Name of the journal page for a day.
+pub fn page_name(date: NaiveDate) -> String {
+    format!("{}/{}", DIR, date.format(DATE_FORMAT))
+}
+
+/// The day a journal page is for, if it's a journal page.
+pub fn date(name: &str) -> Option<NaiveDate> {
+    let date = name.strip_prefix(DIR)?.strip_prefix('/')?;
+    NaiveDate::parse_from_str(date, DATE_FORMAT).ok()
+}
+
+/// The month containing `date`, as weeks starting on Monday. Days
+/// outside the month are `None`. `entries` has to be sorted.
+pub fn calendar(date: NaiveDate, entries: &[NaiveDate]) -> Vec<Vec<Option<Day>>> {
+    let first = date.with_day(1).unwrap_or(date);
+    let mut weeks = vec![];
+    let mut week = vec![None; first.weekday().num_days_from_monday() as usize];
+
+    let mut day = first;
+    while day.month() == first.month() {
+        week.push(Some(Day {
+            date: day,
+            entry: entries.binary_search(&day).is_ok(),
+        }));
+        if week.len() == 7 {
+            weeks.push(week);
+            week = vec![];
+        }
+        day += Duration::days(1);
+    }
+    if !week.is_empty() {
+        week.resize_with(7, || None);
+        weeks.push(week);
+    }
+    weeks
+}
+
+/// The first day of the month before and after `date`'s.
+pub fn prev_next_month(date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let first = date.with_day(1).unwrap_or(date);
+    let prev = (first - Duration::days(1)).with_day(1).unwrap_or(first);
+    let next = (first + Duration::days(31)).with_day(1).unwrap_or(first);
+    (prev, next)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!("journal/2020-06-01", page_name(ymd(2020, 6, 1)));
+        assert_eq!(Some(ymd(2020, 6, 1)), date("journal/2020-06-01"));
+        assert_eq!(None, date("journal/notes"));
+        assert_eq!(None, date("other/2020-06-01"));
+        assert_eq!(None, date("journal2020-06-01"));
+    }
+
+    #[test]
+    fn test_calendar() {
+        // June 2020 started on a Monday and has 30 days
+        let weeks = calendar(ymd(2020, 6, 15), &[ymd(2020, 6, 2), ymd(2020, 7, 1)]);
+        assert_eq!(5, weeks.len());
+        assert_eq!(
+            Some(&Some(Day {
+                date: ymd(2020, 6, 1),
+                entry: false
+            })),
+            weeks[0].first()
+        );
+        assert!(weeks[0][1].as_ref().unwrap().entry);
+        assert_eq!(2, weeks[4].iter().filter(|d| d.is_some()).count());
+        assert!(weeks.iter().all(|w| w.len() == 7));
+
+        // July 2020 started on a Wednesday
+        let weeks = calendar(ymd(2020, 7, 1), &[]);
+        assert!(weeks[0][1].is_none());
+        assert_eq!(ymd(2020, 7, 1), weeks[0][2].as_ref().unwrap().date);
+    }
+
+    #[test]
+    fn test_prev_next_month() {
+        assert_eq!(
+            (ymd(2019, 12, 1), ymd(2020, 2, 1)),
+            prev_next_month(ymd(2020, 1, 31))
+        );
+    }
+}
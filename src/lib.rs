@@ -2,10 +2,22 @@
 pub mod shell;
 pub mod app;
 pub mod db;
+pub mod diagram;
+pub mod diff;
+pub mod front_matter;
 mod hatter;
+pub mod highlight;
+mod index;
+pub mod journal;
 pub mod markdown;
+pub mod math;
 mod page;
+pub mod search;
 pub mod sync;
+pub mod tags;
+pub mod template;
+pub mod upload;
 pub mod utils;
+mod watch;
 
 pub use {crate::hatter::Hatter, page::Page};
@@ -7,12 +7,14 @@ fn main() {
     let mut host = "0.0.0.0";
     let mut port = 8000;
     let mut sync = false;
+    let mut raw_html = false;
 
     while let Some(arg) = args.next() {
         match arg.as_ref() {
             "-v" | "-version" | "--version" => return print_version(),
             "-h" | "-help" | "--help" => return print_help(),
             "-s" | "-sync" | "--sync" => sync = true,
+            "-raw-html" | "--raw-html" => raw_html = true,
             "-H" | "-host" | "--host" => {
                 if let Some(arg) = args.next() {
                     host = arg;
@@ -62,7 +64,11 @@ fn main() {
         }
     }
 
-    let db = db::DB::new(path);
+    let mut db = db::DB::new(path);
+    db.set_raw_html(raw_html);
+    if let Err(e) = db.watch() {
+        eprintln!("Watch Error: {}", e);
+    }
     vial::use_state!(db);
     if let Err(e) = vial::run_with_banner!("~> started at {}", format!("{}:{}", host, port), app) {
         eprintln!("WebServer Error: {}", e);
@@ -81,6 +87,8 @@ Options:
     -H, --host     Host to bind to. Default: 0.0.0.0
     -p, --port     Port to bind to. Default: 8000
     -s, --sync     Automatically sync wiki. Must be a git repo.
+    --raw-html     Don't sanitise HTML in pages. Only use this if
+                   you trust everyone who can edit your wiki.
     -v, --version  Print version.
     -h, --help     Show this message.
 ",
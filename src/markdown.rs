@@ -1,119 +1,1549 @@
 //! Convert wiki Markdown to HTML.
 //!
-//! Supports two syntax extensions:
+//! Supports four syntax extensions:
 //!
 //! - [Page] to link directly to a wiki page.
 //! - #tag to link to a hashtag.
+//! - ![[Page]] on its own line to embed another page, or a section
+//!   of one with ![[Page#Heading]].
+//! - `> [!NOTE] Title` callouts, as in Obsidian and GitHub. Add `-`
+//!   after the `]` to make one collapsible and start closed, or `+`
+//!   to start open.
 //!
+//! Fenced code blocks in a language we know are syntax highlighted,
+//! ` ```dot ` blocks are drawn as diagrams, and `$math$` or
+//! `$$math$$` is rendered to MathML.
+//!
+//! Every heading gets an id, so sections can be linked to, and `toc`
+//! lists them for a table of contents. Ids in the HTML are prefixed,
+//! see `html_id`, and ids written by hand are dropped.
+//!
+//! The HTML is sanitised with an allowlist, so pages can't run
+//! scripts in their readers' browsers, unless the wiki is started
+//! with `--raw-html`.
+
+use {
+    crate::{db::DB, diagram, front_matter, highlight, math, utils::html_encode},
+    linkify::{LinkFinder, LinkKind},
+    pulldown_cmark as markdown,
+    std::{cell::Cell, collections::HashMap, fs, ops::Range},
+};
+
+/// How many levels of ![[Embeds]] inside embeds we'll render.
+const MAX_EMBED_DEPTH: usize = 4;
+
+/// Ids `sanitize` keeps: the ones made by `html_id` and `footnote_id`.
+const ID_PREFIXES: [&str; 2] = ["h-", "fn-"];
 
-use {crate::db::DB, linkify::LinkFinder, pulldown_cmark as markdown, std::borrow::Cow};
+/// MathML elements `math::to_mathml` uses, for the sanitiser.
+const MATHML_TAGS: &[&str] = &[
+    "math",
+    "semantics",
+    "annotation",
+    "mrow",
+    "mi",
+    "mn",
+    "mo",
+    "mtext",
+    "mspace",
+    "msub",
+    "msup",
+    "msubsup",
+    "munder",
+    "mover",
+    "munderover",
+    "mfrac",
+    "msqrt",
+    "mroot",
+    "mtable",
+    "mtr",
+    "mtd",
+    "merror",
+];
+
+/// What `to_html` needs to know about the rest of the wiki to link
+/// to it. See `DB::markdown_context()`.
+#[derive(Debug, Default)]
+pub struct Context {
+    /// Name of every page.
+    pub names: Vec<String>,
+    /// `DB::aliases()`, so [Other Names] link to the real page.
+    pub aliases: HashMap<String, String>,
+    /// Heading ids on each page, by page name.
+    pub headings: HashMap<String, Vec<String>>,
+    /// Where each page is on disk, by page name, for ![[Embeds]].
+    pub paths: HashMap<String, String>,
+    /// The page being rendered, so it can't embed itself.
+    pub page: Option<String>,
+    /// Pass HTML in pages through untouched instead of sanitising it.
+    /// Only for wikis where everyone who can edit is trusted.
+    pub raw_html: bool,
+    /// Where to cache drawn diagrams, if anywhere.
+    pub cache_dir: Option<String>,
+}
+
+impl Context {
+    /// Name of the page a [Wiki Link] points to, if it exists.
+    fn find(&self, page: &str) -> Option<&String> {
+        let name = DB::title_to_name(page);
+        self.names
+            .iter()
+            .find(|n| n.eq_ignore_ascii_case(&name))
+            .or_else(|| self.aliases.get(&name.to_lowercase()))
+    }
+}
 
 /// Convert raw wiki Markdown into HTML.
-/// Takes a list of all wiki pages in the system, for [Link]s.
-pub fn to_html(md: &str, names: &[String]) -> String {
-    let mut options = markdown::Options::empty();
-    options.insert(markdown::Options::ENABLE_TABLES);
-    options.insert(markdown::Options::ENABLE_FOOTNOTES);
-    options.insert(markdown::Options::ENABLE_STRIKETHROUGH);
-    options.insert(markdown::Options::ENABLE_TASKLISTS);
+/// Front matter isn't rendered.
+pub fn to_html(md: &str, context: &Context) -> String {
+    let html = render(md, context, &[]);
+    if context.raw_html {
+        html
+    } else {
+        sanitize(&html)
+    }
+}
 
+/// Strip any tags and attributes we don't know to be safe from
+/// rendered HTML, along with `javascript:` URLs and the like.
+pub fn sanitize(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_generic_attributes(&["class"])
+        // only the ids we make, so pages can't clobber the wiki's own
+        .add_tag_attributes("h1", &["id"])
+        .add_tag_attributes("h2", &["id"])
+        .add_tag_attributes("h3", &["id"])
+        .add_tag_attributes("h4", &["id"])
+        .add_tag_attributes("h5", &["id"])
+        .add_tag_attributes("h6", &["id"])
+        .add_tag_attributes("div", &["id"])
+        // task list checkboxes
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["checked", "disabled", "data-task"])
+        .set_tag_attribute_value("input", "type", "checkbox")
+        .add_tag_attributes("details", &["open"])
+        .add_tags(MATHML_TAGS)
+        .add_tag_attributes("math", &["display", "xmlns"])
+        .add_tag_attributes("mi", &["mathvariant"])
+        .add_tag_attributes("mo", &["fence", "largeop", "movablelimits", "stretchy"])
+        .add_tag_attributes("mover", &["accent"])
+        .add_tag_attributes("munder", &["accentunder"])
+        .add_tag_attributes("mfrac", &["linethickness"])
+        .add_tag_attributes("mspace", &["width"])
+        .add_tag_attributes("mtable", &["columnalign"])
+        .add_tag_attributes("annotation", &["encoding"])
+        // diagrams are SVG images with data: URLs. Images can't run
+        // scripts, so data: URLs are allowed in any image, but only
+        // there
+        .add_url_schemes(&["data"])
+        .attribute_filter(|tag, attr, value| {
+            let data = value.trim_start().to_lowercase().starts_with("data:");
+            let bad_data =
+                data && !(tag == "img" && attr == "src" && value.starts_with("data:image/"));
+            let bad_id = attr == "id" && !ID_PREFIXES.iter().any(|p| value.starts_with(p));
+            if bad_data || bad_id {
+                None
+            } else {
+                Some(value.into())
+            }
+        })
+        .link_rel(None)
+        .clean(html)
+        .to_string()
+}
+
+/// `to_html`, inside the pages in `embedding`.
+fn render(md: &str, context: &Context, embedding: &[String]) -> String {
+    let md = front_matter::strip(md);
+    let toc = toc(md);
+    let (md, maths) = protect_math(md);
+    let md = md.as_str();
+    let ids = toc.iter().map(|h| h.id.clone()).collect::<Vec<_>>();
+    let mut headings = toc.into_iter();
     // are we parsing a wiki link like [Help] or [Solar Power]?
     let mut wiki_link = false;
     // if we are, store the text between [ and ]
     let mut wiki_link_text = String::new();
+    // are we inside an ![[Embed]] paragraph?
+    let mut in_embed = false;
+    // closing tags for the blockquotes we're in: callouts become
+    // <aside>s or <details>, others stay <blockquote>s
+    let mut blockquotes: Vec<Option<&str>> = vec![];
+    // a callout whose first paragraph is coming up next
+    let mut callout_next: Option<Callout> = None;
+    // in a callout's title line: (where the [!NOTE] marker ends, what
+    // closes the title)
+    let mut callout_title: Option<(usize, &str)> = None;
+    // code blocks are left alone
+    let mut in_code_block = false;
+    // ...unless we can highlight or draw them: (language, code so far)
+    let mut highlighting: Option<(String, String)> = None;
+    // task list checkboxes can be ticked on the page itself, but not
+    // in embeds or old revisions
+    let clickable = context.page.is_some() && embedding.is_empty();
+    let mut tasks = 0;
+    // for linking #tags and URLs
+    let mut inline = Inline::default();
+    // is the current event text right after a \, like \#not-a-tag?
+    let escaped = Cell::new(false);
+    // is it the start of a callout's title, right after the [!NOTE]?
+    let title_start = Cell::new(false);
+    // footnotes are numbered in the order they first show up
+    let mut footnotes: HashMap<String, usize> = HashMap::new();
 
-    let parser = markdown::Parser::new_ext(&md, options).map(|event| match event {
-        markdown::Event::Text(text) => {
-            if *text == *"[" && !wiki_link {
-                wiki_link = true;
-                markdown::Event::Text("".into())
-            } else if *text == *"]" && wiki_link {
-                wiki_link = false;
-                let page_name = DB::title_to_name(&wiki_link_text);
-                let link_text = wiki_link_text.clone();
-                wiki_link_text.clear();
-                let (link_class, link_href) = if let Some(idx) = names
-                    .iter()
-                    .position(|n| n.to_ascii_lowercase() == page_name.to_ascii_lowercase())
-                {
-                    ("", format!("/{}", names[idx]))
-                } else {
-                    ("new", format!("/new?name={}", page_name))
-                };
-                markdown::Event::Html(
-                    format!(
-                        r#"<a href="{}" class="{}">{}</a>"#,
-                        link_href, link_class, link_text
-                    )
-                    .into(),
-                )
-            } else if wiki_link {
-                wiki_link_text.push_str(&text);
-                markdown::Event::Text("".into())
-            } else {
-                if text.contains("http://") || text.contains("https://") {
-                    let linked = autolink(&text);
-                    if linked.len() == text.len() {
-                        markdown::Event::Text(text.into())
+    let parser = markdown::Parser::new_ext(md, options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| {
+            escaped.set(is_escaped(md, range.start));
+            title_start.set(false);
+            if in_embed {
+                // skip the rest of the ![[Embed]] paragraph
+                if let markdown::Event::End(markdown::Tag::Paragraph) = event {
+                    in_embed = false;
+                }
+                return None;
+            }
+            if let Some((marker_end, close)) = callout_title {
+                // drop the [!NOTE] marker, but keep the title
+                match event {
+                    markdown::Event::Text(text) if range.start <= marker_end => {
+                        let rest = text.get(marker_end.min(range.end) - range.start..);
+                        return match rest.map(str::trim_start) {
+                            Some(rest) if !rest.is_empty() => {
+                                title_start.set(true);
+                                Some(markdown::Event::Text(rest.to_string().into()))
+                            }
+                            _ => None,
+                        };
+                    }
+                    markdown::Event::SoftBreak | markdown::Event::HardBreak => {
+                        callout_title = None;
+                        let html = format!("{}\n<div class=\"callout-body\">\n<p>", close);
+                        return Some(markdown::Event::Html(html.into()));
+                    }
+                    markdown::Event::End(markdown::Tag::Paragraph) => {
+                        callout_title = None;
+                        let html = format!("{}\n<div class=\"callout-body\">\n", close);
+                        return Some(markdown::Event::Html(html.into()));
+                    }
+                    _ => return Some(event),
+                }
+            }
+            match event {
+                markdown::Event::Start(markdown::Tag::BlockQuote) => {
+                    let quote = &md[range];
+                    let first_line = quote.lines().next().unwrap_or("");
+                    match callout(first_line.trim_start().trim_start_matches('>')) {
+                        Some(callout) if starts_with_paragraph(quote) => {
+                            let html = callout.open_html();
+                            blockquotes.push(Some(callout.close_html()));
+                            callout_next = Some(callout);
+                            Some(markdown::Event::Html(html.into()))
+                        }
+                        _ => {
+                            blockquotes.push(None);
+                            Some(event)
+                        }
+                    }
+                }
+                markdown::Event::End(markdown::Tag::BlockQuote) => match blockquotes.pop() {
+                    Some(Some(close)) => Some(markdown::Event::Html(close.into())),
+                    _ => Some(event),
+                },
+                markdown::Event::Start(markdown::Tag::Paragraph) if callout_next.is_some() => {
+                    let callout = callout_next.take()?;
+                    let (open, close) = callout.title_tags();
+                    callout_title = Some((range.start + callout.len, close));
+                    let html = if callout.title.is_empty() {
+                        format!("{}{}", open, html_encode(&callout.default_title()))
                     } else {
-                        markdown::Event::Html(linked.to_string().into())
+                        open.to_string()
+                    };
+                    Some(markdown::Event::Html(html.into()))
+                }
+                markdown::Event::Start(markdown::Tag::Paragraph) => {
+                    match embed_target(&md[range]) {
+                        Some(target) => {
+                            in_embed = true;
+                            let html = embed_html(target, context, embedding);
+                            Some(markdown::Event::Html(html.into()))
+                        }
+                        None => Some(event),
                     }
-                } else if let Some(idx) = text.find('#') {
-                    // look for and link #hashtags
-                    let linked = text[idx..]
-                        .split(' ')
-                        .map(|word| {
-                            if word.starts_with('#') && word.len() > 1 {
-                                let word = word.trim_start_matches('#');
-                                format!("<a href='/search?tag={}'>#{}</a>", word, word)
-                            } else {
-                                word.into()
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                        .join(" ");
-                    markdown::Event::Html(format!("{}{}", &text[..idx], linked).into())
-                } else {
-                    markdown::Event::Text(text.into())
                 }
+                _ => Some(event),
             }
-        }
-        _ => event,
-    });
+        })
+        .map(|event| {
+            let at = inline.step(&event);
+            match event {
+                markdown::Event::Text(text) => {
+                    if let Some((_, code)) = &mut highlighting {
+                        code.push_str(&text);
+                        markdown::Event::Text("".into())
+                    } else if in_code_block {
+                        markdown::Event::Text(text)
+                    } else if *text == *"[" && !wiki_link {
+                        wiki_link = true;
+                        markdown::Event::Text("".into())
+                    } else if *text == *"]" && wiki_link {
+                        wiki_link = false;
+                        let html = wiki_link_html(&wiki_link_text, context, &ids);
+                        wiki_link_text.clear();
+                        markdown::Event::Html(html.into())
+                    } else if wiki_link {
+                        wiki_link_text.push_str(&text);
+                        markdown::Event::Text("".into())
+                    } else if at.links > 0 {
+                        markdown::Event::Text(text)
+                    } else {
+                        let word_start = at.word_start || title_start.get();
+                        let spans = spans(&text, word_start && !escaped.get());
+                        match spans.as_slice() {
+                            [] | [Span::Text(_)] => markdown::Event::Text(text),
+                            _ => markdown::Event::Html(spans_html(&spans).into()),
+                        }
+                    }
+                }
+                markdown::Event::Html(html) => match maths.replace(&html) {
+                    Some(mathml) => markdown::Event::Html(mathml.into()),
+                    None => markdown::Event::Html(html),
+                },
+                markdown::Event::TaskListMarker(done) if clickable => {
+                    let html = format!(
+                        r#"<input type="checkbox" data-task="{}"{}>"#,
+                        tasks,
+                        if done { " checked" } else { "" }
+                    );
+                    tasks += 1;
+                    markdown::Event::Html(html.into())
+                }
+                markdown::Event::Start(markdown::Tag::CodeBlock(kind)) => {
+                    in_code_block = true;
+                    match kind {
+                        markdown::CodeBlockKind::Fenced(ref info)
+                            if diagram::supports(highlight::language(info))
+                                || highlight::supports(highlight::language(info)) =>
+                        {
+                            let lang = highlight::language(info).to_string();
+                            highlighting = Some((lang, String::new()));
+                            markdown::Event::Text("".into())
+                        }
+                        _ => markdown::Event::Start(markdown::Tag::CodeBlock(kind)),
+                    }
+                }
+                markdown::Event::End(markdown::Tag::CodeBlock(kind)) => {
+                    in_code_block = false;
+                    match highlighting.take() {
+                        Some((lang, code)) if diagram::supports(&lang) => markdown::Event::Html(
+                            diagram::to_html(&code, context.cache_dir.as_deref()).into(),
+                        ),
+                        Some((lang, code)) => markdown::Event::Html(
+                            format!(
+                            "<pre class=\"hl-code\"><code class=\"language-{}\">{}</code></pre>\n",
+                            html_encode(&lang),
+                            highlight::to_html(&code, &lang).unwrap_or_else(|| html_encode(&code))
+                        )
+                            .into(),
+                        ),
+                        None => markdown::Event::End(markdown::Tag::CodeBlock(kind)),
+                    }
+                }
+                markdown::Event::Start(markdown::Tag::Heading(level)) => match headings.next() {
+                    Some(heading) => {
+                        markdown::Event::Html(format!("<h{} id=\"{}\">", level, html_id(&heading.id)).into())
+                    }
+                    None => event,
+                },
+                // like pulldown-cmark's own, but with prefixed ids
+                markdown::Event::FootnoteReference(name) => {
+                    let len = footnotes.len() + 1;
+                    let number = *footnotes.entry(name.to_string()).or_insert(len);
+                    markdown::Event::Html(
+                        format!(
+                            "<sup class=\"footnote-reference\"><a href=\"#{}\">{}</a></sup>",
+                            footnote_id(&name),
+                            number
+                        )
+                        .into(),
+                    )
+                }
+                markdown::Event::Start(markdown::Tag::FootnoteDefinition(name)) => {
+                    let len = footnotes.len() + 1;
+                    let number = *footnotes.entry(name.to_string()).or_insert(len);
+                    markdown::Event::Html(
+                        format!(
+                            "<div class=\"footnote-definition\" id=\"{}\"><sup class=\"footnote-definition-label\">{}</sup>",
+                            footnote_id(&name),
+                            number
+                        )
+                        .into(),
+                    )
+                }
+                _ => event,
+            }
+        });
 
     let mut html_output = String::with_capacity(md.len() * 3 / 2);
     markdown::html::push_html(&mut html_output, parser);
     html_output
 }
 
-/// Autolink https://links in a block of text.
-fn autolink(text: &str) -> Cow<'_, str> {
-    let finder = LinkFinder::new();
-    let links: Vec<_> = finder.links(text).collect();
+/// The first line of a callout, like `> [!WARNING] Title`.
+#[derive(Debug, PartialEq)]
+struct Callout<'a> {
+    /// "note", "warning", etc. Any word works.
+    kind: String,
+    /// Empty if there isn't one.
+    title: &'a str,
+    /// Collapsible callouts are open (`[!TIP]+`) or closed
+    /// (`[!TIP]-`) to begin with.
+    fold: Option<bool>,
+    /// Length of the `[!TIP]+` marker.
+    len: usize,
+}
+
+impl Callout<'_> {
+    fn open_html(&self) -> String {
+        match self.fold {
+            None => format!("<aside class=\"callout callout-{}\">\n", self.kind),
+            Some(open) => format!(
+                "<details class=\"callout callout-{}\"{}>\n",
+                self.kind,
+                if open { " open" } else { "" }
+            ),
+        }
+    }
+
+    fn close_html(&self) -> &'static str {
+        match self.fold {
+            None => "</div>\n</aside>\n",
+            Some(_) => "</div>\n</details>\n",
+        }
+    }
+
+    /// Tags around the title.
+    fn title_tags(&self) -> (&'static str, &'static str) {
+        match self.fold {
+            None => ("<p class=\"callout-title\">", "</p>"),
+            Some(_) => ("<summary class=\"callout-title\">", "</summary>"),
+        }
+    }
+
+    /// "Warning" for `[!WARNING]`.
+    fn default_title(&self) -> String {
+        let mut chars = self.kind.chars();
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Parse the start of a callout's first line, after the `>`.
+fn callout(line: &str) -> Option<Callout<'_>> {
+    let line = line.trim_start();
+    let kind = line.strip_prefix("[!")?.split(']').next()?;
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+    let mut len = kind.len() + 3;
+    let rest = &line[len..];
+    let fold = match rest.chars().next() {
+        Some('+') => Some(true),
+        Some('-') => Some(false),
+        _ => None,
+    };
+    if fold.is_some() {
+        len += 1;
+    }
+    Some(Callout {
+        kind: kind.to_lowercase(),
+        title: line[len..].trim(),
+        fold,
+        len,
+    })
+}
+
+/// Does a blockquote start with a paragraph? A callout's `[!NOTE]`
+/// only counts in one, not in a code block or heading.
+fn starts_with_paragraph(quote: &str) -> bool {
+    let mut events = markdown::Parser::new_ext(quote, options());
+    matches!(
+        (events.next(), events.next()),
+        (
+            Some(markdown::Event::Start(markdown::Tag::BlockQuote)),
+            Some(markdown::Event::Start(markdown::Tag::Paragraph))
+        )
+    )
+}
+
+/// All the #hashtags in a chunk of Markdown, without the '#', in the
+/// order they first appear. Finds the same tags `to_html` links to:
+/// none in code, links, [Wiki Links] or URLs.
+pub fn tags(md: &str) -> Vec<String> {
+    let (md, _) = protect_math(front_matter::strip(md));
+    let mut tags: Vec<String> = vec![];
+    let mut wiki_link = false;
+    let mut in_code_block = false;
+    let mut inline = Inline::default();
+
+    for (event, range) in markdown::Parser::new_ext(&md, options()).into_offset_iter() {
+        let at = inline.step(&event);
+        match event {
+            markdown::Event::Start(markdown::Tag::CodeBlock(_)) => in_code_block = true,
+            markdown::Event::End(markdown::Tag::CodeBlock(_)) => in_code_block = false,
+            markdown::Event::Text(text) => {
+                if in_code_block {
+                    continue;
+                } else if *text == *"[" && !wiki_link {
+                    wiki_link = true;
+                } else if *text == *"]" && wiki_link {
+                    wiki_link = false;
+                } else if !wiki_link && at.links == 0 {
+                    let escaped = is_escaped(&md, range.start);
+                    for span in spans(&text, at.word_start && !escaped) {
+                        match span {
+                            Span::Tag(tag) if !tags.iter().any(|t| t == tag) => {
+                                tags.push(tag.to_string())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    tags
+}
+
+/// Swap `$math$` for placeholders `render` turns into MathML, before
+/// the Markdown parser gets to mangle its `_`s and `*`s. Math in code
+/// is left alone.
+fn protect_math(md: &str) -> (String, math::Formulas) {
+    if !md.contains('$') {
+        return (md.to_string(), math::Formulas::default());
+    }
+    let code = markdown::Parser::new_ext(md, options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            markdown::Event::Code(_) | markdown::Event::Start(markdown::Tag::CodeBlock(_)) => {
+                Some(range)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    math::extract(md, &code)
+}
+
+/// Tick (or untick) the `task`th task list checkbox in a page,
+/// counting from 0 like the `data-task` attributes `to_html` adds.
+/// `None` if there's no such task.
+pub fn toggle_task(md: &str, task: usize, done: bool) -> Option<String> {
+    let body = front_matter::strip(md);
+    let offset = md.len() - body.len();
+    let range = markdown::Parser::new_ext(body, options())
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, markdown::Event::TaskListMarker(_)))
+        .nth(task)
+        .map(|(_, range)| range)?;
+
+    // the range covers "[ ]" or "[x]"
+    let mark = offset + range.start + 1;
+    let mut md = md.to_string();
+    md.replace_range(mark..mark + 1, if done { "x" } else { " " });
+    Some(md)
+}
+
+/// If a word is a #hashtag, the tag without the '#' and whatever
+/// punctuation follows it: "#tag." is ("tag", "."). Tags can be
+/// nested with slashes, like "#project/deadwiki". Numbers aren't
+/// tags, so "PR #42" doesn't have one.
+fn hashtag(word: &str) -> Option<(&str, &str)> {
+    let rest = word.strip_prefix('#')?;
+    let mut len = 0;
+    for part in rest.split('/') {
+        let part_len = part
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(part.len());
+        if part_len == 0 {
+            break;
+        }
+        len += if len == 0 { part_len } else { part_len + 1 };
+        if part_len < part.len() {
+            break;
+        }
+    }
+    let (tag, rest) = rest.split_at(len);
+    if tag.chars().all(|c| c.is_ascii_digit() || c == '/') {
+        None
+    } else {
+        Some((tag, rest))
+    }
+}
+
+/// A heading, for the table of contents.
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    /// 1 for <h1>, 2 for <h2>, etc.
+    pub level: u32,
+    /// Plain text, not HTML.
+    pub title: String,
+    /// Unique on the page.
+    pub id: String,
+}
+
+/// Every heading in a chunk of Markdown, in order. Front matter is
+/// skipped. Ids are made from the heading's text and are the same as
+/// the ones `to_html` uses, so math is protected the same way: a `#`
+/// inside `$$...$$` isn't a heading.
+pub fn toc(md: &str) -> Vec<Heading> {
+    let (md, maths) = protect_math(front_matter::strip(md));
+    let mut headings: Vec<Heading> = vec![];
+    let mut current: Option<Heading> = None;
 
-    if links.is_empty() {
-        return text.into();
+    for event in markdown::Parser::new_ext(&md, options()) {
+        match event {
+            markdown::Event::Start(markdown::Tag::Heading(level)) => {
+                current = Some(Heading {
+                    level,
+                    title: String::new(),
+                    id: String::new(),
+                });
+            }
+            markdown::Event::End(markdown::Tag::Heading(_)) => {
+                if let Some(mut heading) = current.take() {
+                    heading.title = heading.title.trim().to_string();
+                    let slug = slugify(&heading.title);
+                    let mut id = slug.clone();
+                    let mut n = 1;
+                    while headings.iter().any(|h| h.id == id) {
+                        id = format!("{}-{}", slug, n);
+                        n += 1;
+                    }
+                    heading.id = id;
+                    headings.push(heading);
+                }
+            }
+            // [ and ] around wiki links are their own Text events
+            markdown::Event::Text(text) if &*text == "[" || &*text == "]" => {}
+            markdown::Event::Text(text) | markdown::Event::Code(text) => {
+                if let Some(heading) = &mut current {
+                    heading.title.push_str(&text);
+                }
+            }
+            markdown::Event::Html(html) => {
+                if let (Some(heading), Some(tex)) = (&mut current, maths.tex(&html)) {
+                    heading.title.push_str(tex);
+                }
+            }
+            _ => {}
+        }
     }
 
-    let mut out = String::with_capacity(text.len() + (links.len() * 115));
-    let mut fst = true;
+    headings
+}
+
+/// The id attribute for a heading. It's prefixed so a heading like
+/// "Page Body" can't take the id of one of the wiki's own elements.
+pub fn html_id(id: &str) -> String {
+    format!("h-{}", id)
+}
+
+/// The id attribute for a footnote, prefixed like `html_id`.
+fn footnote_id(name: &str) -> String {
+    format!("fn-{}", html_encode(name))
+}
+
+/// "Solar Power: Part 2!" -> "solar-power-part-2"
+fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "section".into()
+    } else {
+        slug.into()
+    }
+}
+
+/// A [Wiki Link], which can point to a section of a page and have
+/// its own text: [Page#Heading|text]. The page can be left out to
+/// link to a section of the current page: [#Heading]
+#[derive(Debug, PartialEq)]
+struct WikiLink<'a> {
+    page: &'a str,
+    heading: Option<&'a str>,
+    text: Option<&'a str>,
+}
+
+impl<'a> WikiLink<'a> {
+    /// Parse the text between the [ and ].
+    fn parse(src: &'a str) -> WikiLink<'a> {
+        let (target, text) = match src.split_once('|') {
+            Some((target, text)) => (target, Some(text.trim())),
+            None => (src, None),
+        };
+        let (page, heading) = match target.split_once('#') {
+            Some((page, heading)) => (page.trim(), Some(heading.trim())),
+            None => (target.trim(), None),
+        };
+        WikiLink {
+            page,
+            heading,
+            text,
+        }
+    }
+}
+
+/// HTML for a [Wiki Link]. Links to pages that don't exist get the
+/// "new" class, and links to sections that don't exist get the
+/// "missing" class. `ids` are the headings on the current page.
+fn wiki_link_html(src: &str, context: &Context, ids: &[String]) -> String {
+    let link = WikiLink::parse(src);
+    let text = html_encode(link.text.unwrap_or(src));
+    let anchor = link.heading.map(slugify);
+
+    let page = if link.page.is_empty() {
+        None
+    } else {
+        match context.find(link.page) {
+            Some(name) => Some(name),
+            None => {
+                return format!(
+                    r#"<a href="/new?name={}" class="new">{}</a>"#,
+                    html_encode(&DB::title_to_name(link.page)),
+                    text
+                )
+            }
+        }
+    };
+
+    let ids = match page {
+        Some(name) => context.headings.get(name).map(|ids| ids.as_slice()),
+        None => Some(ids),
+    };
+    let class = match (&anchor, ids) {
+        (Some(anchor), Some(ids)) if !ids.contains(anchor) => "missing",
+        (Some(_), None) => "missing",
+        _ => "",
+    };
+    let href = format!(
+        "{}{}",
+        page.map(|name| format!("/{}", html_encode(name)))
+            .unwrap_or_default(),
+        anchor
+            .map(|a| format!("#{}", html_id(&a)))
+            .unwrap_or_default()
+    );
+    format!(r#"<a href="{}" class="{}">{}</a>"#, href, class, text)
+}
+
+/// The text between `![[` and `]]`, if a paragraph is just an embed.
+fn embed_target(paragraph: &str) -> Option<&str> {
+    let target = paragraph
+        .trim()
+        .strip_prefix("![[")?
+        .strip_suffix("]]")?
+        .trim();
+    if target.is_empty() || target.contains(['[', ']', '\n']) {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+/// Render another page, or one section of it, to embed in this one.
+/// `embedding` are the pages we're already inside of.
+fn embed_html(src: &str, context: &Context, embedding: &[String]) -> String {
+    let link = WikiLink::parse(src);
+    let src = html_encode(src);
+    let error = |msg: String| format!("<div class=\"embed error\">{}</div>", msg);
+
+    let name = match context.find(link.page) {
+        Some(name) => name,
+        None => {
+            return error(format!(
+                r#"Can't embed <a href="/new?name={0}" class="new">{0}</a>: no such page."#,
+                html_encode(&DB::title_to_name(link.page))
+            ))
+        }
+    };
+    let encoded = html_encode(name);
+    if embedding.contains(name) || context.page.as_ref() == Some(name) {
+        return error(format!("Can't embed {} inside itself.", encoded));
+    } else if embedding.len() >= MAX_EMBED_DEPTH {
+        return error(format!(
+            "Can't embed {}: too many embeds inside embeds.",
+            encoded
+        ));
+    }
+
+    let body = context
+        .paths
+        .get(name)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    let body = front_matter::strip(&body);
+    let (md, url) = match link.heading.map(slugify) {
+        Some(id) => match section(body, &id) {
+            Some(md) => (md, format!("/{}#{}", encoded, html_id(&id))),
+            None => {
+                return error(format!(
+                    r#"Can't embed <a href="/{}" class="missing">{}</a>: no such section."#,
+                    encoded, src
+                ))
+            }
+        },
+        None => (body, format!("/{}", encoded)),
+    };
+
+    let mut inside = embedding.to_vec();
+    inside.push(name.clone());
+    format!(
+        "<div class=\"embed\">\n<div class=\"embed-source\"><a href=\"{}\">{}</a></div>\n{}</div>",
+        url,
+        link.text.map(html_encode).unwrap_or(src),
+        render(md, context, &inside)
+    )
+}
+
+/// One heading and everything under it, up to the next heading at
+/// the same level or higher.
+fn section<'a>(md: &'a str, id: &str) -> Option<&'a str> {
+    let toc = toc(md);
+    let idx = toc.iter().position(|h| h.id == id)?;
+    let level = toc[idx].level;
+
+    let starts = markdown::Parser::new_ext(md, options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            markdown::Event::Start(markdown::Tag::Heading(_)) => Some(range.start),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let start = *starts.get(idx)?;
+    let end = toc
+        .iter()
+        .zip(&starts)
+        .skip(idx + 1)
+        .find(|(h, _)| h.level <= level)
+        .map(|(_, &start)| start)
+        .unwrap_or(md.len());
+    Some(&md[start..end])
+}
+
+/// Find all the [Wiki Links] in a chunk of Markdown, converted to
+/// page names. Doesn't check whether the pages exist.
+pub fn wiki_links(md: &str) -> Vec<String> {
+    let mut links = vec![];
+    for (_, text) in wiki_link_spans(md) {
+        let name = DB::title_to_name(WikiLink::parse(&text).page);
+        if !name.is_empty() && !links.contains(&name) {
+            links.push(name);
+        }
+    }
+    links
+}
+
+/// Point every [Wiki Link] to the page named `from` at the page
+/// titled `to` instead.
+pub fn rewrite_wiki_links(md: &str, from: &str, to: &str) -> String {
+    let mut out = String::with_capacity(md.len());
+    let mut last = 0;
+    for (range, text) in wiki_link_spans(md) {
+        let link = WikiLink::parse(&text);
+        if DB::title_to_name(link.page).eq_ignore_ascii_case(from) {
+            out.push_str(&md[last..range.start]);
+            out.push('[');
+            out.push_str(to);
+            if let Some(heading) = link.heading {
+                out.push('#');
+                out.push_str(heading);
+            }
+            if let Some(text) = link.text {
+                out.push('|');
+                out.push_str(text);
+            }
+            out.push(']');
+            last = range.end;
+        }
+    }
+    out.push_str(&md[last..]);
+    out
+}
+
+/// Location of every [Wiki Link] in the source, brackets and all,
+/// with the text between the brackets. Skips front matter.
+fn wiki_link_spans(md: &str) -> Vec<(Range<usize>, String)> {
+    let mut spans = vec![];
+    let mut start = None;
+    let mut wiki_link_text = String::new();
+
+    let body = front_matter::strip(md);
+    let offset = md.len() - body.len();
+    for (event, range) in markdown::Parser::new_ext(body, options()).into_offset_iter() {
+        let range = range.start + offset..range.end + offset;
+        if let markdown::Event::Text(text) = event {
+            if *text == *"[" && start.is_none() {
+                start = Some(range.start);
+            } else if *text == *"]" && start.is_some() {
+                if let Some(start) = start.take() {
+                    spans.push((start..range.end, wiki_link_text.clone()));
+                }
+                wiki_link_text.clear();
+            } else if start.is_some() {
+                wiki_link_text.push_str(&text);
+            }
+        }
+    }
+
+    spans
+}
+
+/// Markdown extensions we support.
+fn options() -> markdown::Options {
+    let mut options = markdown::Options::empty();
+    options.insert(markdown::Options::ENABLE_TABLES);
+    options.insert(markdown::Options::ENABLE_FOOTNOTES);
+    options.insert(markdown::Options::ENABLE_STRIKETHROUGH);
+    options.insert(markdown::Options::ENABLE_TASKLISTS);
+    options
+}
+
+/// A piece of plain text: a URL or #tag to link, or the text
+/// between them.
+#[derive(Debug, PartialEq)]
+enum Span<'a> {
+    Text(&'a str),
+    Url(&'a str),
+    /// Without the '#'.
+    Tag(&'a str),
+}
+
+/// Split plain text into https://links, #tags and the text between
+/// them, without losing any of it. `word_start` says whether the text
+/// starts a new word, or carries on from the text before it, so
+/// "a*b*#c" has no tags.
+fn spans(text: &str, word_start: bool) -> Vec<Span<'_>> {
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]);
+    let mut spans = vec![];
     let mut last = 0;
-    for link in links {
-        if fst && link.start() > 0 {
-            out.push_str(&text[..link.start()]);
-            fst = false;
+    for link in finder.links(text) {
+        let url = link.as_str();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            continue;
         }
-        let url = &text[link.start()..link.end()];
+        tag_spans(
+            &text[last..link.start()],
+            last == 0 && word_start,
+            &mut spans,
+        );
+        spans.push(Span::Url(url));
         last = link.end();
-        out.push_str(r#"<a href=""#);
-        out.push_str(url);
-        out.push_str(r#"">"#);
-        out.push_str(url);
-        out.push_str("</a>");
+    }
+    tag_spans(&text[last..], last == 0 && word_start, &mut spans);
+    spans
+}
+
+/// `spans` for text without URLs.
+fn tag_spans<'a>(text: &'a str, word_start: bool, spans: &mut Vec<Span<'a>>) {
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        if c != '#' || i < last {
+            continue;
+        }
+        let starts_word = text[..i]
+            .chars()
+            .next_back()
+            .map_or(word_start, starts_word_after);
+        if let Some((tag, _)) = hashtag(&text[i..]).filter(|_| starts_word) {
+            if last < i {
+                spans.push(Span::Text(&text[last..i]));
+            }
+            spans.push(Span::Tag(tag));
+            last = i + 1 + tag.len();
+        }
     }
     if last < text.len() {
-        out.push_str(&text[last..]);
+        spans.push(Span::Text(&text[last..]));
     }
+}
+
+/// Can a #tag start right after this character?
+fn starts_word_after(c: char) -> bool {
+    c.is_whitespace() || c == '('
+}
+
+fn spans_html(spans: &[Span]) -> String {
+    spans
+        .iter()
+        .map(|span| match span {
+            Span::Text(text) => html_encode(text),
+            Span::Url(url) => {
+                let url = html_encode(url);
+                format!("<a href=\"{}\">{}</a>", url, url)
+            }
+            Span::Tag(tag) => {
+                let tag = html_encode(tag);
+                format!("<a href='/search?tag={}'>#{}</a>", tag, tag)
+            }
+        })
+        .collect()
+}
+
+/// Is the character at `pos` escaped with a `\`? `\\` is a backslash,
+/// so it's whether there's an odd number of them.
+fn is_escaped(md: &str, pos: usize) -> bool {
+    let backslashes = md[..pos].bytes().rev().take_while(|&b| b == b'\\').count();
+    backslashes % 2 == 1
+}
+
+/// Where we are in a paragraph, for linking #tags and URLs: only in
+/// plain text outside of links, and #tags only at the start of a word.
+#[derive(Debug, Default, Clone, Copy)]
+struct Inline {
+    /// How many links (or images) we're inside.
+    links: usize,
+    /// Does the next text start a new word?
+    word_start: bool,
+}
 
-    out.into()
+impl Inline {
+    /// Move past an event, returning where we were when it started.
+    fn step(&mut self, event: &markdown::Event) -> Inline {
+        use markdown::{Event, Tag};
+        let before = *self;
+        match event {
+            Event::Text(text) => {
+                if let Some(c) = text.chars().next_back() {
+                    self.word_start = starts_word_after(c);
+                }
+            }
+            Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..)) => self.links += 1,
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                self.links = self.links.saturating_sub(1);
+                self.word_start = false;
+            }
+            // *#tag* is still a tag
+            Event::Start(Tag::Emphasis)
+            | Event::End(Tag::Emphasis)
+            | Event::Start(Tag::Strong)
+            | Event::End(Tag::Strong)
+            | Event::Start(Tag::Strikethrough)
+            | Event::End(Tag::Strikethrough) => {}
+            // inline HTML, like <b> or a math placeholder, is glued to
+            // what comes next
+            Event::Code(_) | Event::Html(_) | Event::FootnoteReference(_) => {
+                self.word_start = false
+            }
+            _ => self.word_start = true,
+        }
+        before
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wiki_links() {
+        assert_eq!(
+            vec!["Solar_Power", "help"],
+            wiki_links("See [Solar Power] and [help], or [Solar Power] again.")
+        );
+        assert!(wiki_links("A [real](/link) and `[code]`.").is_empty());
+        assert_eq!(vec!["Body"], wiki_links("---\ntags: [nope]\n---\n[Body]"));
+        assert_eq!(
+            vec!["Solar_Power"],
+            wiki_links("[Solar Power#Panels] [Solar Power|sun] [#Top]")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_wiki_links() {
+        assert_eq!(
+            "See [energy/Solar] and [help]. [energy/Solar]!",
+            rewrite_wiki_links(
+                "See [Solar Power] and [help]. [solar power]!",
+                "Solar_Power",
+                "energy/Solar"
+            )
+        );
+        assert_eq!(
+            "Not `[Solar Power]`.",
+            rewrite_wiki_links("Not `[Solar Power]`.", "Solar_Power", "x")
+        );
+        assert_eq!(
+            "[x#Part 2|two] [x|one]",
+            rewrite_wiki_links("[a#Part 2|two] [A|one]", "a", "x")
+        );
+        assert_eq!(
+            "---\ntitle: A\n---\nSee [x].",
+            rewrite_wiki_links("---\ntitle: A\n---\nSee [a].", "a", "x")
+        );
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let html = to_html("---\ntitle: Hi\n---\n# Hello", &Context::default());
+        assert_eq!("<h1 id=\"h-hello\">Hello</h1>\n", html);
+    }
+
+    #[test]
+    fn test_toc() {
+        let md =
+            "# Solar Power\n\n## Panels: `2kW`!\n\n## Panels: 2kW\n\n### See [Batteries]\n\n## ???";
+        let toc = toc(md);
+        assert_eq!(
+            vec![
+                "solar-power",
+                "panels-2kw",
+                "panels-2kw-1",
+                "see-batteries",
+                "section"
+            ],
+            toc.iter().map(|h| h.id.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![1, 2, 2, 3, 2],
+            toc.iter().map(|h| h.level).collect::<Vec<_>>()
+        );
+        assert_eq!("Panels: 2kW!", toc[1].title);
+        assert_eq!("See Batteries", toc[3].title);
+
+        let html = to_html(md, &Context::default());
+        assert!(html.contains(r#"<h2 id="h-panels-2kw-1">Panels: 2kW</h2>"#));
+        assert!(html.contains(r#"<h3 id="h-see-batteries">See <a href="/new?name=Batteries""#));
+
+        // a # line in display math isn't a heading
+        let md = "$$\n# x\n$$\n\n# Energy $E=mc^2$\n\n## Next";
+        let headings = super::toc(md);
+        assert_eq!(
+            vec!["energy-emc2", "next"],
+            headings.iter().map(|h| h.id.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!("Energy E=mc^2", headings[0].title);
+        let html = to_html(md, &Context::default());
+        assert!(html.contains(r#"<h1 id="h-energy-emc2">Energy <math"#));
+        assert!(html.contains(r#"<h2 id="h-next">Next</h2>"#));
+        assert!(!html.contains("h-x"));
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut context = Context::default();
+        context.names.push("Kubernetes".into());
+        context.aliases.insert("k8s".into(), "Kubernetes".into());
+        assert_eq!(
+            "<p><a href=\"/Kubernetes\" class=\"\">K8s</a> and <a href=\"/new?name=nope\" class=\"new\">nope</a></p>\n",
+            to_html("[K8s] and [nope]", &context)
+        );
+    }
+
+    #[test]
+    fn test_section_links() {
+        let mut context = Context::default();
+        context.names.push("Solar_Power".into());
+        context
+            .headings
+            .insert("Solar_Power".into(), vec!["panels".into()]);
+        let link = |md: &str| {
+            to_html(md, &context)
+                .trim_start_matches("<p>")
+                .trim_end_matches("</p>\n")
+                .to_string()
+        };
+
+        assert_eq!(
+            r#"<a href="/Solar_Power#h-panels" class="">Solar Power#Panels</a>"#,
+            link("[Solar Power#Panels]")
+        );
+        assert_eq!(
+            r#"<a href="/Solar_Power" class="">the sun</a>"#,
+            link("[Solar Power|the sun]")
+        );
+        assert_eq!(
+            r#"<a href="/Solar_Power#h-panels" class="">panels</a>"#,
+            link("[solar power # panels | panels]")
+        );
+        assert_eq!(
+            r#"<a href="/Solar_Power#h-wind" class="missing">wind</a>"#,
+            link("[Solar Power#Wind|wind]")
+        );
+        assert_eq!(
+            r#"<a href="/new?name=Wind" class="new">breeze</a>"#,
+            link("[Wind#Gusts|breeze]")
+        );
+        assert!(to_html("# Top\n\n[#Top] [#Bottom]", &context).contains(
+            r##"<a href="#h-top" class="">#Top</a> <a href="#h-bottom" class="missing">#Bottom</a>"##
+        ));
+    }
+
+    #[test]
+    fn test_embeds() {
+        let dir = std::env::temp_dir().join("deadwiki-embeds");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let pages = [
+            (
+                "Solar",
+                "# Solar\nIntro\n## Panels\nShiny\n### Cost\nLots\n## Wind\nBreezy\n",
+            ),
+            ("Loop", "Again:\n\n![[Loop]]\n"),
+        ];
+        let mut context = Context::default();
+        for (name, md) in &pages {
+            let path = dir.join(format!("{}.md", name));
+            fs::write(&path, md).unwrap();
+            context.names.push(name.to_string());
+            context
+                .paths
+                .insert(name.to_string(), path.display().to_string());
+        }
+
+        let html = to_html("Before\n\n![[solar#panels]]\n\nAfter", &context);
+        assert!(html.starts_with("<p>Before</p>\n<div class=\"embed\">"));
+        assert!(html.contains(r#"<a href="/Solar#h-panels">solar#panels</a>"#));
+        assert!(html.contains("Shiny") && html.contains("Lots"));
+        assert!(!html.contains("Intro") && !html.contains("Breezy"));
+        assert!(html.ends_with("</div>\n<p>After</p>\n"));
+
+        let html = to_html("![[Solar|the sun]]", &context);
+        assert!(html.contains(r#"<a href="/Solar">the sun</a>"#));
+        assert!(html.contains("Intro") && html.contains("Breezy"));
+
+        // only whole paragraphs embed
+        assert!(!to_html("see ![[Solar]]", &context).contains("embed"));
+
+        let html = to_html("![[Loop]]", &context);
+        assert_eq!(1, html.matches("<div class=\"embed\">").count());
+        assert!(html.contains("Can't embed Loop inside itself."));
+        context.page = Some("Loop".into());
+        let html = to_html("![[Loop]]", &context);
+        assert_eq!(0, html.matches("<div class=\"embed\">").count());
+        assert!(to_html("![[Solar#Tides]]", &context).contains("no such section"));
+        assert!(to_html("![[Nope]]", &context).contains(r#"href="/new?name=Nope""#));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_embed_depth() {
+        let dir = std::env::temp_dir().join("deadwiki-embed-depth");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut context = Context::default();
+        for i in 0..10 {
+            let name = format!("P{}", i);
+            let path = dir.join(format!("{}.md", name));
+            fs::write(&path, format!("![[P{}]]", i + 1)).unwrap();
+            context.names.push(name.clone());
+            context.paths.insert(name, path.display().to_string());
+        }
+
+        let html = to_html("![[P0]]", &context);
+        assert_eq!(
+            MAX_EMBED_DEPTH,
+            html.matches("<div class=\"embed\">").count()
+        );
+        assert!(html.contains("too many embeds"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let context = Context::default();
+        let html = |md| to_html(md, &context);
+
+        assert_eq!("<p>hi</p>\n", html("hi<script>alert(1)</script>"));
+        assert_eq!(
+            "<p>an <img src=\"x.png\"></p>\n",
+            html("an <img src=x.png onerror=alert(1)>")
+        );
+        assert_eq!(
+            "<p><a>click</a></p>\n",
+            html("[click](javascript:alert(1))")
+        );
+        assert_eq!("", html("<iframe src=/evil></iframe>"));
+        assert!(!html("<a href=/x style='position:fixed'>x</a>").contains("style"));
+        assert_eq!(
+            "<input type=\"checkbox\">",
+            html("<input type=text value=password>")
+        );
+        // no clobbering the wiki's own elements
+        assert_eq!("<div>x</div>", html("<div id=\"page-body\">x</div>"));
+        assert_eq!("<h2>x</h2>", html("<h2 id=\"toc\">x</h2>"));
+
+        // what we render ourselves survives
+        assert!(html("# Hi").contains(r#"<h1 id="h-hi">Hi</h1>"#));
+        assert!(html("- [x] done").contains(r#"<input disabled="" checked="" type="checkbox">"#));
+        assert!(html("[New Page]").contains(r#"<a href="/new?name=New_Page" class="new">"#));
+        assert!(html("```rust\nfn main() {}\n```").contains(r#"<code class="language-rust">"#));
+        assert!(
+            html("a[^1]\n\n[^1]: note").contains(r#"<div class="footnote-definition" id="fn-1">"#)
+        );
+        assert!(html("a[^1]\n\n[^1]: note").contains(r##"<a href="#fn-1">1</a>"##));
+
+        let context = Context {
+            raw_html: true,
+            ..Context::default()
+        };
+        assert!(to_html("<b onclick=x>hi</b>", &context).contains("<b onclick=x>"));
+    }
+
+    #[test]
+    fn test_escaping() {
+        // with raw HTML on, so the sanitiser can't hide our mistakes
+        let context = Context {
+            raw_html: true,
+            names: vec!["A".into()],
+            ..Context::default()
+        };
+        let html = |md| to_html(md, &context);
+
+        assert_eq!(
+            "<p><a href=\"/new?name=img_srcx_onerroralert1\" class=\"new\">&lt;img src=x onerror=alert(1)&gt;</a></p>\n",
+            html("[&lt;img src=x onerror=alert(1)&gt;]")
+        );
+        assert_eq!(
+            "<p><a href=\"/A\" class=\"\">&quot;a&quot; &amp; b</a></p>\n",
+            html("[A|\"a\" & b]")
+        );
+        assert_eq!(
+            "<p>1 &lt; 2 <a href='/search?tag=a'>#a</a>&#x27;&gt;&lt;b&gt;</p>\n",
+            html("1 &lt; 2 #a'&gt;&lt;b&gt;")
+        );
+        assert_eq!(
+            "<p>&lt;b&gt; <a href=\"https://x.com/?a=1&amp;b=2\">https://x.com/?a=1&amp;b=2</a></p>\n",
+            html("&lt;b&gt; https://x.com/?a=1&b=2")
+        );
+    }
+
+    #[test]
+    fn test_tags() {
+        let md = "---\ntags: [meta]\n---\n#one and #two_2. #one\n\n\
+            ## heading\n\n##nope #\n\n\
+            `#code` and\n\n```\n#code\n```\n\n\
+            [Page#Heading] see https://x.com/#fragment\n\n> #quoted";
+        assert_eq!(vec!["one", "two_2", "quoted"], tags(md));
+
+        // the same ones are linked: #one twice
+        let html = to_html(md, &Context::default());
+        assert_eq!(4, html.matches("href=\"/search?tag=").count());
+        assert!(html.contains("<code>#code</code>"));
+
+        assert_eq!(
+            vec!["project/deadwiki", "a"],
+            tags("#project/deadwiki/, #a//b #/c")
+        );
+        assert!(html.contains(r#"<a href="/search?tag=two_2">#two_2</a>."#));
+        assert!(html.contains("<pre><code>#code\n</code></pre>"));
+    }
+
+    #[test]
+    fn test_tasks() {
+        let md = "---\ntitle: TODO\n---\n- [ ] one\n- [x] two\n  * [X] nested\n\n> 1. [ ] quoted\n";
+        let context = Context {
+            page: Some("TODO".into()),
+            ..Context::default()
+        };
+        let html = to_html(md, &context);
+        assert!(html.contains(r#"<input data-task="0" type="checkbox">"#));
+        assert!(html.contains(r#"<input data-task="1" checked="" type="checkbox">"#));
+        assert!(html.contains(r#"data-task="3""#));
+
+        // only on the page itself
+        let html = to_html(md, &Context::default());
+        assert!(!html.contains("data-task"));
+        assert!(html.contains(r#"<input disabled="" type="checkbox">"#));
+
+        assert_eq!(
+            Some(md.replacen("- [ ] one", "- [x] one", 1)),
+            toggle_task(md, 0, true)
+        );
+        assert_eq!(
+            Some(md.replacen("* [X] nested", "* [ ] nested", 1)),
+            toggle_task(md, 2, false)
+        );
+        assert_eq!(
+            Some(md.replacen("1. [ ]", "1. [x]", 1)),
+            toggle_task(md, 3, true)
+        );
+        assert_eq!(Some(md.to_string()), toggle_task(md, 1, true));
+        assert_eq!(None, toggle_task(md, 4, true));
+    }
+
+    #[test]
+    fn test_highlighting() {
+        let context = Context::default();
+        let html = to_html(
+            "Code:\n\n```rust,ignore\nlet x = \"#tag [Link]\";\n```\n\nDone",
+            &context,
+        );
+        assert!(
+            html.starts_with("<p>Code:</p>\n<pre class=\"hl-code\"><code class=\"language-rust\">")
+        );
+        assert!(html.contains(r#"<span class="hl-storage hl-type hl-rust">let</span>"#));
+        assert!(html.contains("#tag [Link]"));
+        assert!(!html.contains("<a "));
+        assert!(html.ends_with("</code></pre>\n<p>Done</p>\n"));
+
+        // languages we don't know are left alone
+        assert_eq!(
+            "<pre><code class=\"language-nope\">a &lt; b\n</code></pre>\n",
+            to_html("```nope\na < b\n```", &context)
+        );
+        assert_eq!(
+            "<pre><code>#tag</code></pre>\n",
+            to_html("    #tag", &context)
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        let context = Context::default();
+        let html = to_html("So $a_1 * b_2 \\# x$ and *em* #tag", &context);
+        assert!(html.starts_with(
+            "<p>So <math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"inline\"><semantics><mrow><msub><mi>a</mi><mn>1</mn></msub><mo>∗</mo>"
+        ));
+        assert!(html
+            .contains("<annotation encoding=\"application/x-tex\">a_1 * b_2 \\# x</annotation>"));
+        assert!(
+            html.ends_with("</math> and <em>em</em> <a href=\"/search?tag=tag\">#tag</a></p>\n")
+        );
+        assert_eq!(vec!["tag"], tags("$\\#x$ #tag"));
+        assert!(!to_html("$x$#t2", &context).contains("search?tag"));
+        assert!(tags("$x$#t2").is_empty());
+
+        let html = to_html("$$\n\\frac{a}{b}\n$$\nNext", &context);
+        assert!(html.contains("display=\"block\"><semantics><mfrac><mi>a</mi><mi>b</mi></mfrac>"));
+        assert!(html.ends_with("</math>\nNext</p>\n"));
+
+        // not in code, and not prices
+        assert_eq!(
+            "<p><code>$a_b$</code> costs $5 or $10</p>\n",
+            to_html("`$a_b$` costs $5 or $10", &context)
+        );
+        assert_eq!(
+            "<pre><code>$x$\n</code></pre>\n",
+            to_html("```\n$x$\n```", &context)
+        );
+
+        // placeholders can't be written by hand
+        let html = to_html("<deadwiki-math-0></deadwiki-math-0> $x$", &context);
+        assert_eq!(1, html.matches("<math ").count());
+        assert!(!html.contains("deadwiki-math"));
+    }
+
+    #[test]
+    fn test_diagrams() {
+        let context = Context::default();
+        let html = to_html("```dot\ndigraph { a -> b }\n```", &context);
+        assert!(html.starts_with("<div class=\"diagram\"><img src=\"data:image/svg+xml;base64,"));
+        assert!(html.ends_with("\" alt=\"diagram\"></div>\n"));
+        assert_eq!(
+            "<pre class=\"diagram-error\">Can't parse graph: Expected (graph|digraph)</pre>\n",
+            to_html("```dot\nnope\n```", &context)
+        );
+
+        // other data: URLs aren't allowed
+        assert_eq!(
+            "<p><a>x</a> <img src=\"data:image/png;base64,AA\"></p>\n",
+            to_html(
+                "[x](data:text/html,hi) <img src=\"data:image/png;base64,AA\">",
+                &context
+            )
+        );
+    }
+
+    #[test]
+    fn test_callouts() {
+        let context = Context::default();
+        assert_eq!(
+            "<aside class=\"callout callout-warning\">\n<p class=\"callout-title\">Warning</p>\n<div class=\"callout-body\">\n<p>Don't <em>do</em> it.</p>\n</div>\n</aside>\n",
+            to_html("> [!WARNING]\n> Don't *do* it.", &context)
+        );
+        assert_eq!(
+            "<aside class=\"callout callout-tip\">\n<p class=\"callout-title\">Use <a href=\"/search?tag=git\">#git</a></p>\n<div class=\"callout-body\">\n<p>It helps.</p>\n</div>\n</aside>\n",
+            to_html("> [!tip] Use #git\n>\n> It helps.", &context)
+        );
+
+        // collapsible, and nested
+        assert_eq!(
+            "<details class=\"callout callout-note\">\n<summary class=\"callout-title\">More</summary>\n<div class=\"callout-body\">\n<details class=\"callout callout-info\" open=\"\">\n<summary class=\"callout-title\">Info</summary>\n<div class=\"callout-body\">\n<ul>\n<li>x</li>\n</ul>\n</div>\n</details>\n</div>\n</details>\n",
+            to_html("> [!NOTE]- More\n> > [!INFO]+\n> > - x", &context)
+        );
+
+        // plain quotes are left alone
+        assert_eq!(
+            "<blockquote>\n<p>Said <a href=\"/new?name=NOTE\" class=\"new\">!NOTE</a></p>\n</blockquote>\n",
+            to_html("> Said [!NOTE]", &context)
+        );
+        assert_eq!(
+            "<blockquote>\n<pre><code>[!NOTE] code\n</code></pre>\n</blockquote>\n<p>Hello world here</p>\n",
+            to_html(">     [!NOTE] code\n\nHello world here", &context)
+        );
+        assert!(to_html("> [!TIP] #git", &context)
+            .contains("<p class=\"callout-title\"><a href=\"/search?tag=git\">#git</a></p>"));
+        assert_eq!(vec!["git"], tags("> [!TIP] #git"));
+    }
+
+    #[test]
+    fn test_inline_links() {
+        // (markdown, html inside the <p>, tags)
+        let cases: &[(&str, &str, &[&str])] = &[
+            // not in code, links or images
+            ("`#code` and #tag", "<code>#code</code> and <a href=\"/search?tag=tag\">#tag</a>", &["tag"]),
+            ("[see #tag](/x)", "<a href=\"/x\">see #tag</a>", &[]),
+            ("![#alt](x.png)", "<img src=\"x.png\" alt=\"#alt\">", &[]),
+            ("[#a](/x)#b", "<a href=\"/x\">#a</a>#b", &[]),
+            ("[Page#Heading]", "<a href=\"/new?name=Page\" class=\"new\">Page#Heading</a>", &[]),
+            // numbers aren't tags
+            ("PR #42, #2020/10", "PR #42, #2020/10", &[]),
+            ("#v2 #42a", "<a href=\"/search?tag=v2\">#v2</a> <a href=\"/search?tag=42a\">#42a</a>", &["v2", "42a"]),
+            // only at the start of a word
+            ("a#b &amp;#c `x`#d", "a#b &amp;#c <code>x</code>#d", &[]),
+            ("(#a) *#b*", "(<a href=\"/search?tag=a\">#a</a>) <em><a href=\"/search?tag=b\">#b</a></em>", &["a", "b"]),
+            ("one\n#two", "one\n<a href=\"/search?tag=two\">#two</a>", &["two"]),
+            ("\\#not #yes", "#not <a href=\"/search?tag=yes\">#yes</a>", &["yes"]),
+            ("\\\\#not", "\\#not", &[]),
+            // nor glued to inline HTML or math
+            ("<b>x</b>#t3", "<b>x</b>#t3", &[]),
+            // nothing else in the text changes
+            ("a  #b \t c", "a  <a href=\"/search?tag=b\">#b</a> \t c", &["b"]),
+            // URLs, next to tags
+            (
+                "see https://x.com/#frag and #tag",
+                "see <a href=\"https://x.com/#frag\">https://x.com/#frag</a> and <a href=\"/search?tag=tag\">#tag</a>",
+                &["tag"],
+            ),
+            (
+                "https://a.com and https://b.com",
+                "<a href=\"https://a.com\">https://a.com</a> and <a href=\"https://b.com\">https://b.com</a>",
+                &[],
+            ),
+            ("ftp://a.com x@y.com", "ftp://a.com x@y.com", &[]),
+            // but not inside links
+            ("[https://a.com](https://b.com)", "<a href=\"https://b.com\">https://a.com</a>", &[]),
+        ];
+        for (md, html, expected) in cases {
+            assert_eq!(
+                format!("<p>{}</p>\n", html),
+                to_html(md, &Context::default()),
+                "{}",
+                md
+            );
+            assert_eq!(expected.to_vec(), tags(md), "{}", md);
+        }
+    }
 }
@@ -0,0 +1,1062 @@
+//! LaTeX math, `$inline$` or `$$display$$`, rendered to MathML on the
+//! server so nothing has to be loaded from a CDN.
+//!
+//! Only the LaTeX people actually write in notes is supported:
+//! sub/superscripts, `\frac`, `\sqrt`, Greek letters, the usual
+//! symbols and operators, `\left(` ... `\right)`, accents like `\hat`,
+//! `\text`, `\mathbb` and friends, and matrix/cases environments.
+//! Anything else shows up as an error inside the formula, not the page.
+
+use crate::utils::html_encode;
+
+/// A formula found in a page.
+#[derive(Debug, PartialEq)]
+pub struct Math {
+    /// The LaTeX, without the `$`s.
+    pub tex: String,
+    /// `$$display$$` math gets its own line.
+    pub display: bool,
+}
+
+/// How deep `{...}` and the like can nest before we give up on a
+/// formula, rather than run out of stack.
+const MAX_DEPTH: usize = 100;
+
+/// The formulas taken out of a page by `extract`.
+#[derive(Debug, Default)]
+pub struct Formulas {
+    /// Placeholders are named after this, which isn't anywhere in
+    /// the page, so they can't be written by hand.
+    tag: String,
+    maths: Vec<Math>,
+}
+
+impl Formulas {
+    /// Tag that stands in for the `n`th formula while the rest of the
+    /// page is parsed as Markdown.
+    fn placeholder(&self, n: usize) -> String {
+        format!("<{0}-{1}></{0}-{1}>", self.tag, n)
+    }
+
+    /// What goes where a placeholder's tag was, if `html` is one: the
+    /// opening tag becomes the formula's MathML and the closing tag
+    /// goes away.
+    pub fn replace(&self, html: &str) -> Option<String> {
+        let (close, math) = self.find(html)?;
+        if close {
+            Some(String::new())
+        } else {
+            Some(to_mathml(&math.tex, math.display))
+        }
+    }
+
+    /// The LaTeX of the formula, if `html` is a placeholder's opening
+    /// tag.
+    pub fn tex(&self, html: &str) -> Option<&str> {
+        match self.find(html)? {
+            (false, math) => Some(&math.tex),
+            _ => None,
+        }
+    }
+
+    /// The formula a placeholder's tag stands in for, and whether
+    /// it's the closing tag.
+    fn find(&self, html: &str) -> Option<(bool, &Math)> {
+        let html = html.trim();
+        let (close, n) = match html.strip_prefix("</") {
+            Some(rest) => (true, rest),
+            None => (false, html.strip_prefix('<')?),
+        };
+        let n = n
+            .strip_prefix(self.tag.as_str())?
+            .strip_prefix('-')?
+            .strip_suffix('>')?;
+        Some((close, self.maths.get(n.parse::<usize>().ok()?)?))
+    }
+}
+
+/// Swap every formula in `md` for a placeholder, skipping the byte
+/// ranges in `skip` (code). `$` can be escaped as `\$`. Like pandoc,
+/// inline math can't start or end with a space, and a closing `$`
+/// can't be followed by a digit, so "$5 and $10" is left alone.
+pub fn extract(md: &str, skip: &[std::ops::Range<usize>]) -> (String, Formulas) {
+    let mut out = String::with_capacity(md.len());
+    let mut formulas = Formulas {
+        tag: (0..)
+            .map(|n| format!("deadwiki-math-{}", n))
+            .find(|tag| !md.contains(tag.as_str()))
+            .unwrap_or_default(),
+        maths: vec![],
+    };
+    let bytes = md.as_bytes();
+    let mut last = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(range) = skip.iter().find(|r| r.contains(&i)) {
+            i = range.end;
+            continue;
+        }
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'$' => {
+                let display = bytes.get(i + 1) == Some(&b'$');
+                let start = if display { i + 2 } else { i + 1 };
+                match closing_dollar(md, start, display, skip) {
+                    Some(end) => {
+                        out.push_str(&md[last..i]);
+                        out.push_str(&formulas.placeholder(formulas.maths.len()));
+                        formulas.maths.push(Math {
+                            tex: md[start..end].trim().to_string(),
+                            display,
+                        });
+                        i = if display { end + 2 } else { end + 1 };
+                        last = i;
+                    }
+                    None => i = start,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    out.push_str(&md[last.min(md.len())..]);
+    (out, formulas)
+}
+
+/// Where the `$` or `$$` that closes math starting at `start` is.
+fn closing_dollar(
+    md: &str,
+    start: usize,
+    display: bool,
+    skip: &[std::ops::Range<usize>],
+) -> Option<usize> {
+    let bytes = md.as_bytes();
+    if !display && bytes.get(start).is_none_or(|b| b.is_ascii_whitespace()) {
+        return None;
+    }
+    let mut i = start;
+    while i < bytes.len() {
+        if skip.iter().any(|r| r.contains(&i)) {
+            return None;
+        }
+        match bytes[i] {
+            b'\\' => i += 2,
+            // inline math stays in one paragraph
+            b'\n'
+                if !display
+                    && md[i + 1..]
+                        .trim_start_matches([' ', '\t'])
+                        .starts_with('\n') =>
+            {
+                return None
+            }
+            b'$' if display => {
+                return if bytes.get(i + 1) == Some(&b'$') && i > start {
+                    Some(i)
+                } else {
+                    None
+                };
+            }
+            b'$' => {
+                let ok = !bytes[i - 1].is_ascii_whitespace()
+                    && !bytes.get(i + 1).is_some_and(|b| b.is_ascii_digit());
+                return if ok && i > start { Some(i) } else { None };
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Render LaTeX as a `<math>` element.
+pub fn to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        tokens: tokenize(tex),
+        pos: 0,
+        depth: 0,
+        display,
+    };
+    let body = parser.row(&|_| false);
+    format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"{}\"><semantics>{}<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { "block" } else { "inline" },
+        mrow(body),
+        html_encode(tex)
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// `\frac`, `\alpha`, `\{`
+    Command(String),
+    Number(String),
+    Letter(char),
+    /// Anything else, like `+` or `(`.
+    Symbol(char),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Prime,
+    /// `&`, between matrix cells.
+    Column,
+    /// `\\`, between matrix rows.
+    Row,
+    /// What's inside the `{...}` after `\text` and friends, spaces and
+    /// all.
+    Text(String),
+}
+
+/// Commands whose argument is text, not math.
+fn takes_text(name: &str) -> bool {
+    matches!(
+        name,
+        "text" | "textrm" | "textit" | "textbf" | "mbox" | "operatorname" | "begin" | "end"
+    ) || font(name).is_some()
+}
+
+fn tokenize(tex: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = tex.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => match chars.next() {
+                Some('\\') => Token::Row,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut name = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if !c.is_ascii_alphabetic() {
+                            break;
+                        }
+                        name.push(c);
+                        chars.next();
+                    }
+                    if takes_text(&name) && chars.peek() == Some(&'{') {
+                        chars.next();
+                        let mut text = String::new();
+                        let mut depth = 0;
+                        for c in chars.by_ref() {
+                            match c {
+                                '{' => depth += 1,
+                                '}' if depth == 0 => break,
+                                '}' => depth -= 1,
+                                _ => {}
+                            }
+                            text.push(c);
+                        }
+                        tokens.push(Token::Command(name));
+                        Token::Text(text)
+                    } else {
+                        Token::Command(name)
+                    }
+                }
+                Some(c) => Token::Command(c.to_string()),
+                None => continue,
+            },
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '\'' => Token::Prime,
+            '&' => Token::Column,
+            c if c.is_whitespace() => continue,
+            c if c.is_ascii_digit() => {
+                let mut num = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    num.push(c);
+                    chars.next();
+                }
+                Token::Number(num)
+            }
+            c if c.is_alphabetic() => Token::Letter(c),
+            c => Token::Symbol(c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How many `atom`s we're inside.
+    depth: usize,
+    display: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parse elements until `stop` matches the next token, or we run
+    /// out of them.
+    fn row(&mut self, stop: &dyn Fn(&Token) -> bool) -> Vec<String> {
+        let mut row = vec![];
+        while let Some(token) = self.peek() {
+            if stop(token) {
+                break;
+            }
+            // stray } and & in the wrong place are ignored
+            if *token == Token::Close || *token == Token::Column || *token == Token::Row {
+                self.pos += 1;
+                continue;
+            }
+            row.push(self.scripted());
+        }
+        row
+    }
+
+    /// `{...}` as one element, or the next element if there are no
+    /// braces.
+    fn group(&mut self) -> String {
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let row = self.row(&|t| *t == Token::Close);
+            self.pos += 1;
+            mrow(row)
+        } else if let Some(Token::Number(n)) = self.tokens.get_mut(self.pos) {
+            // like TeX, \frac12 is ½
+            if n.len() > 1 {
+                let digit = n.remove(0);
+                return format!("<mn>{}</mn>", digit);
+            }
+            self.atom().unwrap_or_default()
+        } else {
+            self.atom().unwrap_or_else(|| "<mrow></mrow>".into())
+        }
+    }
+
+    /// The text argument of `\text` and friends.
+    fn text_group(&mut self) -> String {
+        match self.next() {
+            Some(Token::Text(text)) => text,
+            Some(Token::Letter(c)) | Some(Token::Symbol(c)) => c.to_string(),
+            Some(Token::Number(n)) => n,
+            _ => String::new(),
+        }
+    }
+
+    /// An element, with any sub/superscripts after it.
+    fn scripted(&mut self) -> String {
+        let limits = match self.peek() {
+            Some(Token::Command(c)) => self.display && has_limits(c),
+            _ => false,
+        };
+        let base = self.atom().unwrap_or_else(|| "<mrow></mrow>".into());
+
+        let mut sub = None;
+        let mut sup: Option<String> = None;
+        let mut primes = String::new();
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.group());
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.group());
+                }
+                Some(Token::Prime) => {
+                    self.pos += 1;
+                    primes.push('′');
+                }
+                _ => break,
+            }
+        }
+        if !primes.is_empty() {
+            let primes = format!("<mo>{}</mo>", primes);
+            sup = Some(match sup {
+                Some(sup) => format!("<mrow>{}{}</mrow>", primes, sup),
+                None => primes,
+            });
+        }
+
+        let (under, over, both) = if limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+        }
+    }
+
+    /// One element, without scripts. Everything that nests goes
+    /// through here, so this is where we stop if it nests too deep.
+    fn atom(&mut self) -> Option<String> {
+        if self.depth >= MAX_DEPTH {
+            // skip the rest, so everything we're inside ends too
+            self.pos = self.tokens.len();
+            return Some("<merror><mtext>Too deeply nested</mtext></merror>".into());
+        }
+        self.depth += 1;
+        let atom = self.bare_atom();
+        self.depth -= 1;
+        atom
+    }
+
+    fn bare_atom(&mut self) -> Option<String> {
+        Some(match self.next()? {
+            Token::Open => {
+                self.pos -= 1;
+                self.group()
+            }
+            Token::Number(n) => format!("<mn>{}</mn>", n),
+            Token::Letter(c) => format!("<mi>{}</mi>", html_encode(&c.to_string())),
+            Token::Symbol(c) => mo(&operator(c).to_string()),
+            Token::Prime => "<mo>′</mo>".into(),
+            Token::Text(text) => format!("<mtext>{}</mtext>", html_encode(&text)),
+            Token::Command(name) => self.command(&name),
+            // scripts with nothing to attach to
+            Token::Sup | Token::Sub => {
+                self.pos -= 1;
+                "<mrow></mrow>".into()
+            }
+            Token::Close | Token::Column | Token::Row => "<mrow></mrow>".into(),
+        })
+    }
+
+    fn command(&mut self, name: &str) -> String {
+        if let Some(s) = identifier(name) {
+            let variant = if s.chars().next().is_some_and(char::is_uppercase) {
+                " mathvariant=\"normal\""
+            } else {
+                ""
+            };
+            return format!("<mi{}>{}</mi>", variant, s);
+        } else if let Some(s) = symbol(name) {
+            return mo(s);
+        } else if let Some(s) = big_operator(name) {
+            return format!("<mo largeop=\"true\" movablelimits=\"true\">{}</mo>", s);
+        } else if FUNCTIONS.contains(&name) {
+            return format!("<mi>{}</mi>", name);
+        } else if let Some(width) = space(name) {
+            return format!("<mspace width=\"{}\"></mspace>", width);
+        } else if let Some((accent, over)) = accent(name) {
+            let base = self.group();
+            let tag = if over { "mover" } else { "munder" };
+            let attr = if over { "accent" } else { "accentunder" };
+            return format!(
+                "<{0} {1}=\"true\">{2}<mo stretchy=\"true\">{3}</mo></{0}>",
+                tag, attr, base, accent
+            );
+        } else if let Some(style) = font(name) {
+            let text = self.text_group();
+            return if style.is_empty() {
+                format!("<mi mathvariant=\"normal\">{}</mi>", html_encode(&text))
+            } else {
+                let styled = text.chars().map(|c| styled(c, style)).collect::<String>();
+                format!("<mi>{}</mi>", html_encode(&styled))
+            };
+        }
+
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let num = self.group();
+                let den = self.group();
+                format!("<mfrac>{}{}</mfrac>", num, den)
+            }
+            "binom" => {
+                let top = self.group();
+                let bottom = self.group();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom
+                )
+            }
+            "sqrt" => {
+                if self.peek() == Some(&Token::Symbol('[')) {
+                    self.pos += 1;
+                    let index = mrow(self.row(&|t| *t == Token::Symbol(']')));
+                    self.pos += 1;
+                    let base = self.group();
+                    format!("<mroot>{}{}</mroot>", base, index)
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.group())
+                }
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                format!("<mtext>{}</mtext>", html_encode(&self.text_group()))
+            }
+            "operatorname" => format!(
+                "<mi mathvariant=\"normal\">{}</mi>",
+                html_encode(&self.text_group())
+            ),
+            "bmod" => mo("mod"),
+            "pmod" => format!(
+                "<mrow><mo>(</mo><mi>mod</mi>{}<mo>)</mo></mrow>",
+                self.group()
+            ),
+            "left" => {
+                let open = self.delimiter();
+                let body = self.row(&|t| *t == Token::Command("right".into()));
+                self.pos += 1;
+                let close = self.delimiter();
+                format!(
+                    "<mrow>{}{}{}</mrow>",
+                    fence(&open),
+                    body.join(""),
+                    fence(&close)
+                )
+            }
+            // \right without a \left
+            "right" => {
+                self.delimiter();
+                "<mrow></mrow>".into()
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" => {
+                let delim = self.delimiter();
+                fence(&delim)
+            }
+            "begin" => self.environment(),
+            "displaystyle" | "textstyle" | "limits" | "nolimits" => "<mrow></mrow>".into(),
+            _ => format!("<merror><mtext>\\{}</mtext></merror>", html_encode(name)),
+        }
+    }
+
+    /// The delimiter after `\left`, `\right` or `\big`. `.` means
+    /// none.
+    fn delimiter(&mut self) -> String {
+        match self.next() {
+            Some(Token::Symbol('.')) => String::new(),
+            Some(Token::Symbol(c)) => c.to_string(),
+            Some(Token::Command(name)) => match name.as_str() {
+                "{" | "}" => name,
+                "|" => "‖".into(),
+                _ => symbol(&name).unwrap_or("").to_string(),
+            },
+            _ => String::new(),
+        }
+    }
+
+    /// `\begin{matrix} a & b \\ c & d \end{matrix}`
+    fn environment(&mut self) -> String {
+        let env = self.text_group();
+        let mut rows = vec![];
+        let is_end = |t: &Token| {
+            *t == Token::Column || *t == Token::Row || *t == Token::Command("end".into())
+        };
+        loop {
+            let mut cells = vec![];
+            loop {
+                cells.push(mrow(self.row(&is_end)));
+                if self.peek() == Some(&Token::Column) {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+            rows.push(cells);
+            match self.next() {
+                Some(Token::Row) => continue,
+                Some(Token::Command(_)) => {
+                    self.text_group();
+                    break;
+                }
+                _ => break,
+            }
+        }
+        // a trailing \\ leaves an empty row
+        if rows.len() > 1
+            && rows
+                .last()
+                .is_some_and(|r| r.len() == 1 && r[0] == "<mrow></mrow>")
+        {
+            rows.pop();
+        }
+
+        let align = if env == "cases" || env.starts_with("align") {
+            " columnalign=\"left\""
+        } else {
+            ""
+        };
+        let table = format!(
+            "<mtable{}>{}</mtable>",
+            align,
+            rows.iter()
+                .map(|cells| format!(
+                    "<mtr>{}</mtr>",
+                    cells
+                        .iter()
+                        .map(|c| format!("<mtd>{}</mtd>", c))
+                        .collect::<String>()
+                ))
+                .collect::<String>()
+        );
+        let (open, close) = match env.trim_end_matches('*') {
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            "cases" => ("{", ""),
+            _ => ("", ""),
+        };
+        if open.is_empty() && close.is_empty() {
+            table
+        } else {
+            format!("<mrow>{}{}{}</mrow>", fence(open), table, fence(close))
+        }
+    }
+}
+
+fn mrow(mut row: Vec<String>) -> String {
+    if row.len() == 1 {
+        row.remove(0)
+    } else {
+        format!("<mrow>{}</mrow>", row.join(""))
+    }
+}
+
+fn mo(op: &str) -> String {
+    format!("<mo>{}</mo>", html_encode(op))
+}
+
+/// A stretchy bracket, or nothing.
+fn fence(delim: &str) -> String {
+    if delim.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+            html_encode(delim)
+        )
+    }
+}
+
+/// Plain characters that look better as their Unicode versions.
+fn operator(c: char) -> char {
+    match c {
+        '-' => '−',
+        '*' => '∗',
+        c => c,
+    }
+}
+
+/// Do `\sum` and friends put their scripts above and below?
+fn has_limits(name: &str) -> bool {
+    matches!(
+        name,
+        "sum"
+            | "prod"
+            | "coprod"
+            | "bigcup"
+            | "bigcap"
+            | "bigoplus"
+            | "bigotimes"
+            | "lim"
+            | "limsup"
+            | "liminf"
+            | "max"
+            | "min"
+            | "sup"
+            | "inf"
+            | "det"
+            | "gcd"
+            | "Pr"
+    )
+}
+
+/// Function names, set upright.
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "lim", "limsup", "liminf", "max", "min", "sup", "inf", "det",
+    "dim", "ker", "gcd", "deg", "arg", "hom", "Pr",
+];
+
+/// Letters and constants.
+fn identifier(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "emptyset" | "varnothing" => "∅",
+        "aleph" => "ℵ",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        _ => return None,
+    })
+}
+
+/// Operators, relations, arrows and punctuation.
+fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "pm" => "±",
+        "mp" => "∓",
+        "times" => "×",
+        "div" => "÷",
+        "cdot" => "⋅",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "∙",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "ll" => "≪",
+        "gg" => "≫",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "wedge" | "land" => "∧",
+        "vee" | "lor" => "∨",
+        "neg" | "lnot" => "¬",
+        "forall" => "∀",
+        "exists" => "∃",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "implies" => "⟹",
+        "mapsto" => "↦",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "mid" => "∣",
+        "parallel" => "∥",
+        "perp" => "⊥",
+        "angle" => "∠",
+        "triangle" => "△",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "vert" => "|",
+        "Vert" => "‖",
+        "colon" => ":",
+        "prime" => "′",
+        "{" => "{",
+        "}" => "}",
+        "|" => "‖",
+        "$" => "$",
+        "#" => "#",
+        "%" => "%",
+        "&" => "&",
+        "_" => "_",
+        _ => return None,
+    })
+}
+
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        _ => return None,
+    })
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," => "0.1667em",
+        ":" | ">" => "0.2222em",
+        ";" => "0.2778em",
+        " " => "0.25em",
+        "!" => "-0.1667em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        _ => return None,
+    })
+}
+
+/// The accent character, and whether it goes over (or under).
+fn accent(name: &str) -> Option<(&'static str, bool)> {
+    Some(match name {
+        "hat" | "widehat" => ("^", true),
+        "bar" | "overline" => ("¯", true),
+        "vec" | "overrightarrow" => ("→", true),
+        "dot" => ("˙", true),
+        "ddot" => ("¨", true),
+        "tilde" | "widetilde" => ("~", true),
+        "underline" => ("_", false),
+        _ => return None,
+    })
+}
+
+/// `\mathbb` and friends, by the style `styled` knows. `""` is
+/// upright.
+fn font(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "mathrm" => "",
+        "mathit" => "italic",
+        "mathbf" | "boldsymbol" => "bold",
+        "mathbb" => "double-struck",
+        "mathcal" | "mathscr" => "script",
+        "mathfrak" => "fraktur",
+        "mathsf" => "sans-serif",
+        "mathtt" => "monospace",
+        _ => return None,
+    })
+}
+
+/// A letter or digit in one of Unicode's mathematical alphabets.
+fn styled(c: char, style: &str) -> char {
+    // a few letters were in Unicode before the rest of their alphabet
+    let special = match (style, c) {
+        ("double-struck", 'C') => Some('ℂ'),
+        ("double-struck", 'H') => Some('ℍ'),
+        ("double-struck", 'N') => Some('ℕ'),
+        ("double-struck", 'P') => Some('ℙ'),
+        ("double-struck", 'Q') => Some('ℚ'),
+        ("double-struck", 'R') => Some('ℝ'),
+        ("double-struck", 'Z') => Some('ℤ'),
+        ("script", 'B') => Some('ℬ'),
+        ("script", 'E') => Some('ℰ'),
+        ("script", 'F') => Some('ℱ'),
+        ("script", 'H') => Some('ℋ'),
+        ("script", 'I') => Some('ℐ'),
+        ("script", 'L') => Some('ℒ'),
+        ("script", 'M') => Some('ℳ'),
+        ("script", 'R') => Some('ℛ'),
+        ("script", 'e') => Some('ℯ'),
+        ("script", 'g') => Some('ℊ'),
+        ("script", 'o') => Some('ℴ'),
+        ("fraktur", 'C') => Some('ℭ'),
+        ("fraktur", 'H') => Some('ℌ'),
+        ("fraktur", 'I') => Some('ℑ'),
+        ("fraktur", 'R') => Some('ℜ'),
+        ("fraktur", 'Z') => Some('ℨ'),
+        ("italic", 'h') => Some('ℎ'),
+        _ => None,
+    };
+    if let Some(c) = special {
+        return c;
+    }
+
+    // where each alphabet's A, a and 0 are
+    let (upper, lower, digit) = match style {
+        "bold" => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+        "italic" => (0x1D434, 0x1D44E, None),
+        "double-struck" => (0x1D538, 0x1D552, Some(0x1D7D8)),
+        "script" => (0x1D49C, 0x1D4B6, None),
+        "fraktur" => (0x1D504, 0x1D51E, None),
+        "sans-serif" => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+        "monospace" => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+        _ => return c,
+    };
+    let code = match c {
+        'A'..='Z' => upper + (c as u32 - 'A' as u32),
+        'a'..='z' => lower + (c as u32 - 'a' as u32),
+        '0'..='9' => match digit {
+            Some(digit) => digit + (c as u32 - '0' as u32),
+            None => return c,
+        },
+        _ => return c,
+    };
+    std::char::from_u32(code).unwrap_or(c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Just the MathML for the formula, without the <math> wrapper.
+    fn ml(tex: &str) -> String {
+        let html = to_mathml(tex, false);
+        let start = html.find("<semantics>").unwrap() + "<semantics>".len();
+        let end = html.find("<annotation").unwrap();
+        html[start..end].to_string()
+    }
+
+    #[test]
+    fn test_extract() {
+        let md = "Cost $5 and $10. So $x_1 = a_2$ and \\$y$ or `$code$`\n\n$$\n\\sum x\n$$";
+        let code = md.find('`').unwrap()..md.rfind('`').unwrap() + 1;
+        let (out, formulas) = extract(md, &[code]);
+        assert_eq!(
+            vec![
+                Math {
+                    tex: "x_1 = a_2".into(),
+                    display: false
+                },
+                Math {
+                    tex: "\\sum x".into(),
+                    display: true
+                },
+            ],
+            formulas.maths
+        );
+        assert_eq!(
+            format!(
+                "Cost $5 and $10. So {} and \\$y$ or `$code$`\n\n{}",
+                formulas.placeholder(0),
+                formulas.placeholder(1)
+            ),
+            out
+        );
+
+        // no closing $, or not in the same paragraph
+        assert!(extract("a $b\n\nc$ d", &[]).1.maths.is_empty());
+        assert!(extract("$ x $", &[]).1.maths.is_empty());
+        assert_eq!(1, extract("$a\nb$", &[]).1.maths.len());
+    }
+
+    #[test]
+    fn test_replace() {
+        let (out, formulas) = extract("<deadwiki-math-0></deadwiki-math-0> $x$", &[]);
+        assert_eq!(
+            "<deadwiki-math-0></deadwiki-math-0> <deadwiki-math-1-0></deadwiki-math-1-0>",
+            out
+        );
+        assert_eq!(
+            Some(to_mathml("x", false)),
+            formulas.replace("<deadwiki-math-1-0>")
+        );
+        assert_eq!(
+            Some(String::new()),
+            formulas.replace("</deadwiki-math-1-0>")
+        );
+        // written by hand, or no such formula
+        assert_eq!(None, formulas.replace("<deadwiki-math-0>"));
+        assert_eq!(None, formulas.replace("<deadwiki-math-1-1>"));
+        assert_eq!(None, formulas.replace("<b>"));
+    }
+
+    #[test]
+    fn test_to_mathml() {
+        assert_eq!(
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><semantics><mi>x</mi><annotation encoding=\"application/x-tex\">x</annotation></semantics></math>",
+            to_mathml("x", true)
+        );
+        assert_eq!(
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><msub><mi>y</mi><mrow><mi>i</mi><mi>j</mi></mrow></msub></mrow>",
+            ml("x^2 + y_{ij}")
+        );
+        assert_eq!(
+            "<mfrac><mrow><mo>−</mo><mi>b</mi><mo>±</mo><msqrt><mrow><msup><mi>b</mi><mn>2</mn></msup><mo>−</mo><mn>4</mn><mi>a</mi><mi>c</mi></mrow></msqrt></mrow><mrow><mn>2</mn><mi>a</mi></mrow></mfrac>",
+            ml("\\frac{-b \\pm \\sqrt{b^2 - 4ac}}{2a}")
+        );
+        assert_eq!(
+            "<mrow><msubsup><mo largeop=\"true\" movablelimits=\"true\">∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></msubsup><msub><mi>α</mi><mi>i</mi></msub></mrow>",
+            ml("\\sum_{i=1}^n \\alpha_i")
+        );
+        assert!(to_mathml("\\sum_{i=1}^n", true).contains("<munderover>"));
+        assert_eq!("<msup><mi>f</mi><mo>′′</mo></msup>", ml("f''"));
+        assert_eq!(
+            "<mrow><mi>x</mi><mo>∈</mo><mi>ℝ</mi><mtext>if so</mtext></mrow>",
+            ml("x \\in \\mathbb{R} \\text{if so}")
+        );
+        assert_eq!("<mroot><mi>x</mi><mn>3</mn></mroot>", ml("\\sqrt[3]{x}"));
+        assert_eq!(
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mfrac><mn>1</mn><mn>2</mn></mfrac><mo fence=\"true\" stretchy=\"true\">]</mo></mrow>",
+            ml("\\left( \\frac12 \\right]")
+        );
+        assert_eq!(
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>",
+            ml("\\begin{pmatrix} a & b \\\\ c & d \\\\ \\end{pmatrix}")
+        );
+        assert_eq!(
+            "<mover accent=\"true\"><mi>v</mi><mo stretchy=\"true\">→</mo></mover>",
+            ml("\\vec v")
+        );
+        assert_eq!("<mrow><mi>sin</mi><mi>θ</mi></mrow>", ml("\\sin\\theta"));
+        assert_eq!(
+            "<mrow><mi>a</mi><mo>&lt;</mo><merror><mtext>\\nope</mtext></merror></mrow>",
+            ml("a < \\nope")
+        );
+        // unbalanced input doesn't panic
+        for tex in &[
+            "{",
+            "}",
+            "x^",
+            "\\frac",
+            "\\left(",
+            "\\begin{matrix}",
+            "_",
+            "\\",
+        ] {
+            to_mathml(tex, false);
+        }
+
+        // nor does nesting too deep
+        assert!(ml(&"{".repeat(3000)).contains("<merror><mtext>Too deeply nested</mtext></merror>"));
+        assert!(ml(&"\\sqrt".repeat(3000)).contains("<merror>"));
+        assert!(!ml(&format!("{}x{}", "{".repeat(50), "}".repeat(50))).contains("<merror>"));
+    }
+}
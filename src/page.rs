@@ -1,8 +1,18 @@
 //! Single Wiki Page
 
 use {
+    crate::{
+        front_matter::FrontMatter,
+        utils::{html_encode, url_encode},
+    },
     hatter::{Object, Value},
-    std::{cell::RefCell, fs, rc::Rc},
+    std::{
+        cell::{OnceCell, RefCell},
+        collections::{hash_map::DefaultHasher, BTreeMap},
+        fs,
+        hash::{Hash, Hasher},
+        rc::Rc,
+    },
 };
 
 #[derive(Debug)]
@@ -10,6 +20,7 @@ pub struct Page {
     path: String,
     root: String,
     body: RefCell<String>,
+    front_matter: OnceCell<FrontMatter>,
 }
 
 /// thing.into()
@@ -19,7 +30,10 @@ impl From<Page> for Value {
     }
 }
 
-/// In Hatter: page.title, page.url, page.path
+/// In Hatter: page.title, page.url, page.path, page.tags, and any
+/// other key from the page's front matter. Hatter doesn't escape
+/// anything, so everything is HTML encoded here, and the URL is
+/// percent-encoded too.
 impl Object for Page {
     fn typename(&self) -> &str {
         "Page"
@@ -27,12 +41,24 @@ impl Object for Page {
 
     fn get(&self, key: &str) -> Option<Value> {
         match key {
-            "title" => Some(self.title().into()),
-            "name" => Some(self.name().into()),
-            "url" => Some(self.url().into()),
-            "path" => Some(self.path().into()),
+            "title" => Some(html_encode(&self.title()).into()),
+            "name" => Some(html_encode(self.name()).into()),
+            "url" => Some(html_encode(&url_encode(&self.url())).into()),
+            "path" => Some(html_encode(self.path()).into()),
             "body" => Some(self.body().into()),
-            _ => None,
+            "version" => Some(self.version().into()),
+            "tags" => Some(encode_all(self.front_matter().tags()).into()),
+            "aliases" => Some(encode_all(self.front_matter().aliases()).into()),
+            "created" => Some(
+                self.front_matter()
+                    .created()
+                    .map(|s| html_encode(&s))
+                    .into(),
+            ),
+            "meta" => Some(yaml_to_hatter(&serde_yaml::Value::Mapping(
+                self.front_matter().fields().clone(),
+            ))),
+            _ => self.front_matter().get(key).map(yaml_to_hatter),
         }
     }
 }
@@ -43,6 +69,7 @@ impl Page {
             root: root.as_ref().into(),
             path: path.as_ref().into(),
             body: RefCell::new(String::new()),
+            front_matter: OnceCell::new(),
         }
     }
 
@@ -74,7 +101,30 @@ impl Page {
         self.body.borrow().clone()
     }
 
+    /// Changes whenever the page's content does, so we can tell if
+    /// someone else saved it since we read it.
+    pub fn version(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.body().hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Metadata from the top of the page, if any. Only parsed once.
+    pub fn front_matter(&self) -> &FrontMatter {
+        self.front_matter
+            .get_or_init(|| FrontMatter::parse(&self.body()))
+    }
+
+    /// The title from the page's front matter, or one made from its
+    /// name if there isn't one.
     pub fn title(&self) -> String {
+        self.front_matter()
+            .title()
+            .unwrap_or_else(|| self.title_from_name())
+    }
+
+    /// "linux_laptops" -> "Linux Laptops"
+    pub fn title_from_name(&self) -> String {
         self.name()
             .split('_')
             .map(|part| {
@@ -87,7 +137,7 @@ impl Page {
                         capitalize(last)
                     )
                 } else {
-                    capitalize(&part)
+                    capitalize(part)
                 }
             })
             .collect::<Vec<_>>()
@@ -101,6 +151,28 @@ impl Page {
     }
 }
 
+/// HTML encode a list of strings, for Hatter.
+fn encode_all(list: Vec<String>) -> Vec<String> {
+    list.iter().map(|s| html_encode(s)).collect()
+}
+
+/// Front matter values, in Hatter, with strings HTML encoded.
+fn yaml_to_hatter(value: &serde_yaml::Value) -> Value {
+    use serde_yaml::Value as Yaml;
+    match value {
+        Yaml::Null => Value::None,
+        Yaml::Bool(b) => (*b).into(),
+        Yaml::Number(n) => n.as_f64().unwrap_or(0.0).into(),
+        Yaml::String(s) => html_encode(s).into(),
+        Yaml::Sequence(items) => items.iter().map(yaml_to_hatter).collect::<Vec<_>>().into(),
+        Yaml::Mapping(map) => map
+            .iter()
+            .filter_map(|(k, v)| Some((html_encode(k.as_str()?), yaml_to_hatter(v))))
+            .collect::<BTreeMap<_, _>>()
+            .into(),
+    }
+}
+
 /// Capitalize the first letter of a string.
 fn capitalize(s: &str) -> String {
     format!(
@@ -128,4 +200,70 @@ mod test {
         assert_eq!(page.url(), "/linux_laptops");
         assert_eq!(page.path, "./wiki/linux_laptops.md");
     }
+
+    #[test]
+    fn test_front_matter() {
+        let dir = std::env::temp_dir().join("deadwiki-page-front-matter");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("ios_notes.md"),
+            "---\ntitle: iOS Notes\nmood: ok\n---\nHi",
+        )
+        .unwrap();
+        let page = Page::new(
+            dir.display().to_string(),
+            dir.join("ios_notes.md").display().to_string(),
+        );
+        assert_eq!("iOS Notes", page.title());
+        assert_eq!("Ios Notes", page.title_from_name());
+        assert_eq!(
+            Some("ok".to_string()),
+            page.get("mood").map(|v| v.to_string())
+        );
+        assert!(page.get("nope").is_none());
+
+        // Hatter doesn't escape, so we do
+        fs::write(
+            dir.join("evil.md"),
+            "---\ntitle: <script>x</script>\ntags: [\"<b>\"]\nmood: \"<i>\"\n---\nHi",
+        )
+        .unwrap();
+        let page = Page::new(
+            dir.display().to_string(),
+            dir.join("evil.md").display().to_string(),
+        );
+        assert_eq!("<script>x</script>", page.title());
+        assert_eq!(
+            Some("&lt;script&gt;x&lt;/script&gt;".to_string()),
+            page.get("title").map(|v| v.to_string())
+        );
+        assert_eq!(
+            Some("&lt;i&gt;".to_string()),
+            page.get("mood").map(|v| v.to_string())
+        );
+        assert!(page
+            .get("tags")
+            .map(|v| v.to_string())
+            .unwrap()
+            .contains("&lt;b&gt;"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_name() {
+        let page = Page::new("./wiki", "./wiki/a\" onmouseover=\"x.md");
+        assert_eq!("a\" onmouseover=\"x", page.name());
+        assert_eq!(
+            Some("a&quot; onmouseover=&quot;x".to_string()),
+            page.get("name").map(|v| v.to_string())
+        );
+        assert_eq!(
+            Some("/a%22%20onmouseover%3D%22x".to_string()),
+            page.get("url").map(|v| v.to_string())
+        );
+        assert_eq!(
+            Some("./wiki/a&quot; onmouseover=&quot;x.md".to_string()),
+            page.get("path").map(|v| v.to_string())
+        );
+    }
 }
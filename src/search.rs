@@ -0,0 +1,328 @@
+//! Full-text search across wiki page titles and bodies.
+//!
+//! Queries are words and "quoted phrases". A page has to match all of
+//! them, in its title or body. Results are ranked by how often they
+//! match (and whether they match the title) and come with a snippet
+//! of the page with the matches highlighted.
+
+use {
+    crate::{front_matter, index::Entry, utils::html_encode, Page},
+    std::{collections::HashMap, fs},
+};
+
+/// How many words to show before the first match in a snippet.
+const SNIPPET_BEFORE: usize = 8;
+
+/// How many words a snippet has, max.
+const SNIPPET_WORDS: usize = 32;
+
+/// How much more a title match counts than a body match.
+const TITLE_BOOST: f32 = 2.0;
+
+/// A search result.
+#[derive(Debug)]
+pub struct Hit {
+    pub page: Page,
+    pub score: f32,
+    /// HTML. Matches are wrapped in <mark>.
+    pub snippet: String,
+}
+
+/// A page we've indexed.
+struct Doc {
+    path: String,
+    body: String,
+    /// Byte offsets of each word in the body.
+    words: Vec<(usize, usize)>,
+    title: Vec<String>,
+}
+
+/// Inverted index: maps every word to the pages it appears in, and
+/// where.
+pub struct SearchIndex {
+    root: String,
+    /// `index::Index` version this was built from.
+    version: usize,
+    docs: Vec<Doc>,
+    terms: HashMap<String, HashMap<usize, Vec<usize>>>,
+}
+
+impl SearchIndex {
+    /// An empty index that will never be current.
+    pub fn empty(root: &str) -> SearchIndex {
+        SearchIndex {
+            root: root.to_string(),
+            version: usize::MAX,
+            docs: vec![],
+            terms: HashMap::new(),
+        }
+    }
+
+    /// Read every page in the page index and build a search index.
+    pub fn build(root: &str, version: usize, entries: &[Entry]) -> SearchIndex {
+        let mut index = SearchIndex::empty(root);
+        index.version = version;
+
+        for (id, entry) in entries.iter().enumerate() {
+            let body = fs::read_to_string(&entry.path).unwrap_or_default();
+            let body = front_matter::strip(&body).to_string();
+            let words = tokenize(&body);
+            for (pos, &(start, end)) in words.iter().enumerate() {
+                index
+                    .terms
+                    .entry(body[start..end].to_lowercase())
+                    .or_default()
+                    .entry(id)
+                    .or_default()
+                    .push(pos);
+            }
+            let title = tokenize(&entry.title)
+                .into_iter()
+                .map(|(start, end)| entry.title[start..end].to_lowercase())
+                .collect();
+            index.docs.push(Doc {
+                path: entry.path.clone(),
+                body,
+                words,
+                title,
+            });
+        }
+
+        index
+    }
+
+    /// `index::Index` version this was built from.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Find pages matching every word and "phrase" in the query, best
+    /// matches first.
+    pub fn search(&self, query: &str) -> Vec<Hit> {
+        let clauses = parse_query(query);
+        if clauses.is_empty() {
+            return vec![];
+        }
+
+        // doc id => (score, highlighted word positions)
+        let mut found: Option<HashMap<usize, (f32, Vec<usize>)>> = None;
+        for clause in &clauses {
+            let matches = self.find_clause(clause);
+            found = Some(match found {
+                None => matches,
+                Some(found) => found
+                    .into_iter()
+                    .filter_map(|(id, (score, mut marks))| {
+                        let (s, m) = matches.get(&id)?;
+                        marks.extend(m);
+                        Some((id, (score + s, marks)))
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut hits = found
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, (score, marks))| Hit {
+                page: Page::new(&self.root, &self.docs[id].path),
+                score,
+                snippet: self.snippet(id, &marks),
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.page.name().cmp(b.page.name()))
+        });
+        hits
+    }
+
+    /// Find all the pages that contain a word or phrase. Returns each
+    /// page's score and the positions of the matching words.
+    fn find_clause(&self, clause: &[String]) -> HashMap<usize, (f32, Vec<usize>)> {
+        let mut found = HashMap::new();
+        let postings = clause
+            .iter()
+            .map(|term| self.terms.get(term))
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+
+        let n = self.docs.len() as f32;
+        let idf = |df: usize| (1.0 + n / df.max(1) as f32).ln();
+        let weight = postings
+            .iter()
+            .map(|p| idf(p.len()))
+            .sum::<f32>()
+            .max(idf(1));
+
+        for (id, doc) in self.docs.iter().enumerate() {
+            let mut marks = vec![];
+            if let Some(starts) = postings.first().and_then(|p| p.get(&id)) {
+                for &start in starts {
+                    let follows = postings.iter().enumerate().skip(1).all(|(i, p)| {
+                        p.get(&id)
+                            .is_some_and(|positions| positions.contains(&(start + i)))
+                    });
+                    if follows {
+                        marks.extend(start..start + clause.len());
+                    }
+                }
+            }
+
+            let in_title = doc
+                .title
+                .windows(clause.len())
+                .any(|window| window == clause);
+            if marks.is_empty() && !in_title {
+                continue;
+            }
+
+            let tf = (marks.len() / clause.len()) as f32;
+            let mut score = weight * tf / (tf + 1.0);
+            if in_title {
+                score += weight * TITLE_BOOST;
+            }
+            found.insert(id, (score, marks));
+        }
+
+        found
+    }
+
+    /// HTML snippet of a page's body around its first match.
+    fn snippet(&self, id: usize, marks: &[usize]) -> String {
+        let doc = &self.docs[id];
+        if doc.words.is_empty() {
+            return String::new();
+        }
+
+        let first = marks.iter().min().copied().unwrap_or(0);
+        let start = first.saturating_sub(SNIPPET_BEFORE);
+        let end = (start + SNIPPET_WORDS).min(doc.words.len());
+
+        let mut out = String::new();
+        if start > 0 {
+            out.push('…');
+        }
+        let mut last = doc.words[start].0;
+        for pos in start..end {
+            let (from, to) = doc.words[pos];
+            out.push_str(&html_encode(&doc.body[last..from]));
+            if marks.contains(&pos) {
+                out.push_str("<mark>");
+                out.push_str(&html_encode(&doc.body[from..to]));
+                out.push_str("</mark>");
+            } else {
+                out.push_str(&html_encode(&doc.body[from..to]));
+            }
+            last = to;
+        }
+        if end < doc.words.len() {
+            out.push('…');
+        } else {
+            out.push_str(&html_encode(doc.body[last..].trim_end()));
+        }
+        out
+    }
+}
+
+/// Byte offsets of every word in a string.
+fn tokenize(text: &str) -> Vec<(usize, usize)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            if start.is_none() {
+                start = Some(i);
+            }
+        } else if let Some(s) = start.take() {
+            words.push((s, i));
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
+
+/// Split a query into words and "quoted phrases", each a list of
+/// lowercase words.
+fn parse_query(query: &str) -> Vec<Vec<String>> {
+    query
+        .split('"')
+        .enumerate()
+        .flat_map(|(i, part)| {
+            let words = tokenize(part)
+                .into_iter()
+                .map(|(start, end)| part[start..end].to_lowercase())
+                .collect::<Vec<_>>();
+            if i % 2 == 1 {
+                // inside quotes
+                vec![words]
+            } else {
+                words.into_iter().map(|w| vec![w]).collect()
+            }
+        })
+        .filter(|clause| !clause.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(dir: &std::path::Path, name: &str, body: &str) -> Entry {
+        let path = dir.join(format!("{}.md", name)).display().to_string();
+        fs::write(&path, body).unwrap();
+        Entry {
+            path,
+            name: name.into(),
+            title: name.replace('_', " "),
+            tags: vec![],
+            links: vec![],
+            aliases: vec![],
+            headings: vec![],
+            stamp: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            vec![vec!["solar"], vec!["power", "bank"], vec!["usb"]],
+            parse_query(r#"Solar "power bank" USB"#)
+        );
+        assert!(parse_query(r#" "" "#).is_empty());
+    }
+
+    #[test]
+    fn test_search() {
+        let dir = std::env::temp_dir().join(format!("deadwiki-search-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let entries = vec![
+            entry(&dir, "solar_power", "Panels & batteries. A power bank."),
+            entry(&dir, "batteries", "Lithium batteries store power."),
+            entry(&dir, "bank", "Money goes in the bank, power goes out."),
+        ];
+        let index = SearchIndex::build(&format!("{}/", dir.display()), 0, &entries);
+
+        let hits = index.search("batteries");
+        assert_eq!(2, hits.len());
+        assert_eq!("batteries", hits[0].page.name());
+        assert_eq!("solar_power", hits[1].page.name());
+        assert_eq!(
+            "Panels &amp; <mark>batteries</mark>. A power bank.",
+            hits[1].snippet
+        );
+
+        let hits = index.search(r#""power bank""#);
+        assert_eq!(1, hits.len());
+        assert_eq!("solar_power", hits[0].page.name());
+
+        assert_eq!(1, index.search("solar").len());
+        assert_eq!(0, index.search("power nope").len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
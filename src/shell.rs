@@ -6,7 +6,7 @@ macro_rules! shell {
         crate::shell::shell("sh", &["-c", $cmd.as_ref()])
     }};
     ($cmd:expr, $($arg:tt)+) => {
-        shell!(format!($cmd, $($arg)+));
+        shell!(format!($cmd, $($arg)+))
     };
 }
 
@@ -20,9 +20,6 @@ pub fn shell(path: &str, args: &[&str]) -> Result<String, std::io::Error> {
     };
     match String::from_utf8(out) {
         Ok(s) => Ok(s),
-        Err(e) => Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        )),
+        Err(e) => Err(std::io::Error::other(e.to_string())),
     }
 }
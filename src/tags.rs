@@ -0,0 +1,109 @@
+//! #Tags can be nested with slashes, like `#project/deadwiki`. A page
+//! tagged `project/deadwiki` counts as tagged `project` too, so
+//! searching for #project finds it.
+
+use std::collections::HashMap;
+
+/// One tag in the tree on the /tags page.
+#[derive(Debug, PartialEq)]
+pub struct Tag {
+    /// Full name, like "project/deadwiki".
+    pub name: String,
+    /// 0 for top level tags, 1 for the ones nested under them, etc.
+    pub depth: usize,
+    /// How many pages have this tag, or one nested under it.
+    pub count: usize,
+}
+
+impl Tag {
+    /// Last part of the name, like "deadwiki".
+    pub fn label(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+}
+
+/// Is `tag` the same as `parent`, or nested under it?
+pub fn is_under(tag: &str, parent: &str) -> bool {
+    tag.strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// A tag and every tag it's nested under: "a/b/c" is "a", "a/b" and
+/// "a/b/c".
+pub fn with_parents(tag: &str) -> Vec<&str> {
+    tag.match_indices('/')
+        .map(|(idx, _)| &tag[..idx])
+        .chain(std::iter::once(tag))
+        .collect()
+}
+
+/// Every tag the pages use, including the ones they're nested under,
+/// sorted so nested tags come right after their parent. `pages` is
+/// the list of tags on each page.
+pub fn tree<'a, I>(pages: I) -> Vec<Tag>
+where
+    I: IntoIterator<Item = &'a [String]>,
+{
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for tags in pages {
+        let mut seen = tags
+            .iter()
+            .flat_map(|t| with_parents(t))
+            .collect::<Vec<_>>();
+        seen.sort_unstable();
+        seen.dedup();
+        for tag in seen {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    let mut tree = counts
+        .into_iter()
+        .map(|(name, count)| Tag {
+            name: name.to_string(),
+            depth: name.matches('/').count(),
+            count,
+        })
+        .collect::<Vec<_>>();
+    tree.sort_by(|a, b| a.name.split('/').cmp(b.name.split('/')));
+    tree
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_under() {
+        assert!(is_under("project", "project"));
+        assert!(is_under("project/deadwiki", "project"));
+        assert!(!is_under("projects", "project"));
+        assert!(!is_under("project", "project/deadwiki"));
+        assert_eq!(vec!["a", "a/b", "a/b/c"], with_parents("a/b/c"));
+    }
+
+    #[test]
+    fn test_tree() {
+        let pages = [
+            vec!["project/deadwiki".to_string(), "project/phd".into()],
+            vec!["project/deadwiki/bugs".into(), "project-ideas".into()],
+            vec!["misc".into()],
+        ];
+        let tree = tree(pages.iter().map(|tags| tags.as_slice()));
+        let tree = tree
+            .iter()
+            .map(|t| (t.name.as_str(), t.label(), t.depth, t.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("misc", "misc", 0, 1),
+                ("project", "project", 0, 2),
+                ("project/deadwiki", "deadwiki", 1, 2),
+                ("project/deadwiki/bugs", "bugs", 2, 1),
+                ("project/phd", "phd", 1, 1),
+                ("project-ideas", "project-ideas", 0, 1),
+            ],
+            tree
+        );
+    }
+}
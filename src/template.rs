@@ -0,0 +1,81 @@
+//! Templates for new wiki pages, kept as Markdown files in the wiki's
+//! `templates/` directory.
+//!
+//! `templates/default.md` is used for every new page, unless there's a
+//! default for the directory the page is in, like
+//! `templates/journal/default.md`. Other templates are picked by name:
+//! `/new?template=meeting`.
+//!
+//! Placeholders:
+//!
+//! - `{{title}}`: the new page's title, like "Team Sync"
+//! - `{{name}}`: its name, like "meetings/Team_Sync"
+//! - `{{parent}}`: the directory it's in, like "meetings"
+//! - `{{date}}`: today, like 2020-06-01
+//! - `{{time}}`: right now, like 14:05
+
+use chrono::NaiveDateTime;
+
+/// Where templates live, inside the wiki root.
+pub const DIR: &str = "templates";
+
+/// Name of a directory's default template.
+pub const DEFAULT: &str = "default";
+
+/// Fill in a template's placeholders for a new page named `name`.
+pub fn expand(src: &str, name: &str, now: NaiveDateTime) -> String {
+    let name = name.trim().trim_start_matches('/');
+    let (parent, base) = name.rsplit_once('/').unwrap_or(("", name));
+    src.replace("{{title}}", base.replace('_', " ").trim())
+        .replace("{{name}}", name)
+        .replace("{{parent}}", parent)
+        .replace("{{date}}", &now.format("%Y-%m-%d").to_string())
+        .replace("{{time}}", &now.format("%H:%M").to_string())
+}
+
+/// Names of the default templates that could apply to a page, most
+/// specific first: "a/b/page" checks "a/b/default", "a/default",
+/// then "default".
+pub fn defaults_for(name: &str) -> Vec<String> {
+    let name = name.trim().trim_start_matches('/');
+    let mut dirs = name.split('/').collect::<Vec<_>>();
+    dirs.pop();
+    let mut defaults = vec![];
+    while !dirs.is_empty() {
+        defaults.push(format!("{}/{}", dirs.join("/"), DEFAULT));
+        dirs.pop();
+    }
+    defaults.push(DEFAULT.to_string());
+    defaults
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, chrono::NaiveDate};
+
+    #[test]
+    fn test_expand() {
+        let now = NaiveDate::from_ymd_opt(2020, 6, 1)
+            .and_then(|d| d.and_hms_opt(14, 5, 0))
+            .unwrap();
+        assert_eq!(
+            "# Team Sync\nmeetings on 2020-06-01 at 14:05 ({{nope}})",
+            expand(
+                "# {{title}}\n{{parent}} on {{date}} at {{time}} ({{nope}})",
+                "meetings/Team_Sync",
+                now
+            )
+        );
+        assert_eq!("Top: ", expand("{{name}}: {{parent}}", "Top", now));
+    }
+
+    #[test]
+    fn test_defaults_for() {
+        assert_eq!(
+            vec!["a/b/default", "a/default", "default"],
+            defaults_for("a/b/page")
+        );
+        assert_eq!(vec!["journal/default", "default"], defaults_for("journal/"));
+        assert_eq!(vec!["default"], defaults_for("page"));
+    }
+}
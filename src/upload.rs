@@ -0,0 +1,232 @@
+//! Attachments uploaded from the editor with `multipart/form-data`.
+//!
+//! vial only gives us request bodies as UTF-8, so the editor sends
+//! file contents with `Content-Transfer-Encoding: base64`. Plain text
+//! parts work too, which is what a regular <form> sends for text
+//! files.
+
+use std::io;
+
+/// Biggest file we'll accept, in bytes.
+pub const MAX_SIZE: usize = 10 * 1024 * 1024;
+
+/// Biggest request body we'll read an upload from. The editor sends
+/// files as base64, which is 4/3 the size, plus the other form fields.
+pub const MAX_BODY: usize = MAX_SIZE / 3 * 4 + 64 * 1024;
+
+/// Longest filename we'll keep, in characters, not counting any
+/// suffix added to make it unique.
+const MAX_FILENAME: usize = 100;
+
+/// Extensions we link to with ![image](syntax).
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "svg", "webp", "bmp"];
+
+/// Extensions browsers show without running anything in them. Other
+/// attachments, like `.html` or `.svg`, are served as downloads so
+/// they can't run scripts on the wiki's origin. (`<img>` tags still
+/// show SVGs, since images can't run scripts.)
+const INLINE_EXTENSIONS: [&str; 14] = [
+    "png", "jpg", "jpeg", "gif", "webp", "bmp", "ico", "pdf", "txt", "mp3", "mp4", "ogg", "wav",
+    "webm",
+];
+
+/// One field of a multipart form.
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Split a `multipart/form-data` body into its parts. `content_type`
+/// is the request's Content-Type header, which has the boundary.
+pub fn parse(content_type: &str, body: &str) -> io::Result<Vec<Part>> {
+    let boundary = content_type
+        .split(';')
+        .map(|s| s.trim())
+        .find_map(|s| s.strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"'))
+        .filter(|b| !b.is_empty())
+        .ok_or_else(|| invalid("Missing multipart boundary"))?;
+    let delimiter = format!("--{}", boundary);
+
+    let mut parts = vec![];
+    for chunk in body.split(&delimiter).skip(1) {
+        if chunk.starts_with("--") {
+            break;
+        }
+        let chunk = chunk.strip_prefix("\r\n").unwrap_or(chunk);
+        let chunk = chunk.strip_suffix("\r\n").unwrap_or(chunk);
+        let (head, content) = chunk
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| invalid("Malformed multipart body"))?;
+
+        let mut name = None;
+        let mut filename = None;
+        let mut base64 = false;
+        for line in head.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            if key.eq_ignore_ascii_case("content-disposition") {
+                name = param(value, "name");
+                filename = param(value, "filename");
+            } else if key.eq_ignore_ascii_case("content-transfer-encoding") {
+                base64 = value.trim().eq_ignore_ascii_case("base64");
+            }
+        }
+
+        let data = if base64 {
+            base64::decode(content.split_whitespace().collect::<String>())
+                .map_err(|e| invalid(&format!("Bad base64: {}", e)))?
+        } else {
+            content.as_bytes().to_vec()
+        };
+        parts.push(Part {
+            name: name.ok_or_else(|| invalid("Multipart field without a name"))?,
+            filename,
+            data,
+        });
+    }
+
+    Ok(parts)
+}
+
+/// Turn whatever the browser says a file is called into something
+/// safe to put on disk: no directories, no hidden files, no spaces,
+/// and never a `.md` that would show up as a wiki page.
+pub fn safe_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let mut safe = name
+        .trim()
+        .replace(' ', "_")
+        .chars()
+        .filter(|&c| c.is_alphanumeric() || c == '.' || c == '_' || c == '-')
+        .take(MAX_FILENAME)
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string();
+
+    if safe.is_empty() {
+        safe = "file".into();
+    }
+    if !safe.contains('.') {
+        // `show` only serves files with an extension
+        safe.push_str(".bin");
+    } else if safe.to_lowercase().ends_with(".md") {
+        safe.push_str(".txt");
+    }
+    safe
+}
+
+/// Markdown that shows an uploaded file: an image for images, a
+/// regular link for everything else.
+pub fn markdown_link(filename: &str, url: &str) -> String {
+    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    let url = url.replace(' ', "%20");
+    if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+        format!("![{}]({})", filename, url)
+    } else {
+        format!("[{}]({})", filename, url)
+    }
+}
+
+/// Whether a path is in a page's `<page>_files/` directory, where
+/// uploads go.
+pub fn is_attachment(path: &str) -> bool {
+    path.trim_start_matches('/')
+        .rsplit_once('/')
+        .is_some_and(|(dir, _)| dir.ends_with("_files"))
+}
+
+/// Whether a file can be shown in the browser, rather than downloaded.
+pub fn is_inline(filename: &str) -> bool {
+    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    INLINE_EXTENSIONS.contains(&ext.as_str())
+}
+
+/// Find `key="value"` in a header like Content-Disposition.
+fn param(header: &str, key: &str) -> Option<String> {
+    header.split(';').find_map(|p| {
+        let (k, v) = p.trim().split_once('=')?;
+        if k.trim() == key {
+            Some(v.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let body = "--XX\r\n\
+            Content-Disposition: form-data; name=\"page\"\r\n\r\n\
+            Nested/Some Page\r\n\
+            --XX\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a b.png\"\r\n\
+            Content-Type: image/png\r\n\
+            Content-Transfer-Encoding: base64\r\n\r\n\
+            iVBORw0KGgo=\r\n\
+            --XX--\r\n";
+        let parts = parse("multipart/form-data; boundary=XX", body).unwrap();
+        assert_eq!(2, parts.len());
+        assert_eq!("page", parts[0].name);
+        assert_eq!(None, parts[0].filename);
+        assert_eq!(b"Nested/Some Page", parts[0].data.as_slice());
+        assert_eq!("file", parts[1].name);
+        assert_eq!(Some("a b.png".into()), parts[1].filename);
+        assert_eq!(b"\x89PNG\r\n\x1a\n", parts[1].data.as_slice());
+
+        assert!(parse("multipart/form-data", body).is_err());
+    }
+
+    #[test]
+    fn test_safe_filename() {
+        assert_eq!("cat_pic.png", safe_filename("cat pic.png"));
+        assert_eq!("passwd.bin", safe_filename("../../etc/passwd"));
+        assert_eq!("evil.jpg", safe_filename("C:\\Users\\me\\evil.jpg"));
+        assert_eq!("htaccess.bin", safe_filename(".htaccess"));
+        assert_eq!("notes.md.txt", safe_filename("notes.md"));
+        assert_eq!("file.bin", safe_filename("<>"));
+    }
+
+    #[test]
+    fn test_is_attachment() {
+        assert!(is_attachment("/page_files/a.svg"));
+        assert!(is_attachment("sub/page_files/a.html"));
+        assert!(!is_attachment("html/about.html"));
+        assert!(!is_attachment("style.css"));
+        assert!(!is_attachment("page_files"));
+    }
+
+    #[test]
+    fn test_is_inline() {
+        assert!(is_inline("cat.PNG"));
+        assert!(is_inline("notes.md.txt"));
+        assert!(!is_inline("evil.html"));
+        assert!(!is_inline("evil.svg"));
+        assert!(!is_inline("evil.xhtml"));
+        assert!(!is_inline("file.bin"));
+    }
+
+    #[test]
+    fn test_markdown_link() {
+        assert_eq!(
+            "![a.PNG](/x_files/a.PNG)",
+            markdown_link("a.PNG", "/x_files/a.PNG")
+        );
+        assert_eq!(
+            "[a.pdf](/my%20page_files/a.pdf)",
+            markdown_link("a.pdf", "/my page_files/a.pdf")
+        );
+    }
+}
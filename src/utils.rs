@@ -17,3 +17,29 @@ pub fn html_encode(html: &str) -> String {
         .replace('"', "&quot;")
         .replace('\'', "&#x27;")
 }
+
+/// Percent-encode a path or query value for a URL. Only letters,
+/// digits, `-._~` and `/` are left as they are.
+pub fn url_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url_encode() {
+        assert_eq!("sub/my_page-1.md", url_encode("sub/my_page-1.md"));
+        assert_eq!("a%20b%26c%23d%3Fe%2Bf", url_encode("a b&c#d?e+f"));
+        assert_eq!("%22%3E%C3%A9", url_encode("\">é"));
+    }
+}
@@ -0,0 +1,109 @@
+//! Watch the wiki directory for changes and keep the page index up
+//! to date, so edits made outside the web UI (vim, git pull, etc)
+//! show up on the next page load.
+//!
+//! Uses inotify on Linux. Everywhere else the index checks for
+//! changed pages on every read, and re-reads only those.
+
+use {
+    crate::index::Index,
+    std::{io, sync::Arc},
+};
+
+#[cfg(target_os = "linux")]
+use {
+    inotify::{EventMask, Inotify, WatchDescriptor, WatchMask},
+    std::{collections::HashMap, fs, path::PathBuf, thread},
+};
+
+/// Start watching in a background thread.
+#[cfg(target_os = "linux")]
+pub fn start(index: Arc<Index>) -> io::Result<()> {
+    let mut inotify = Inotify::init()?;
+    let mut dirs = HashMap::new();
+    add_watches(&mut inotify, PathBuf::from(index.root()), &mut dirs)?;
+    index.set_watched(true);
+
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(e) => {
+                    eprintln!("Watch Error: {}", e);
+                    index.set_watched(false);
+                    return;
+                }
+            };
+
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    index.invalidate();
+                    continue;
+                } else if event.mask.contains(EventMask::IGNORED) {
+                    dirs.remove(&event.wd);
+                    continue;
+                }
+
+                let name = match event.name {
+                    Some(name) if !name.to_string_lossy().starts_with('.') => name,
+                    _ => continue,
+                };
+                let path = match dirs.get(&event.wd) {
+                    Some(dir) => dir.join(name),
+                    None => continue,
+                };
+
+                if event.mask.contains(EventMask::ISDIR) {
+                    if event
+                        .mask
+                        .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                    {
+                        if let Err(e) = add_watches(&mut inotify, path, &mut dirs) {
+                            eprintln!("Watch Error: {}", e);
+                        }
+                    }
+                    index.invalidate();
+                } else if path.extension().is_some_and(|ext| ext == "md") {
+                    index.refresh(&path.to_string_lossy());
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Without inotify, the index checks for changes on every read.
+#[cfg(not(target_os = "linux"))]
+pub fn start(_index: Arc<Index>) -> io::Result<()> {
+    Ok(())
+}
+
+/// inotify isn't recursive, so we need to watch every directory.
+#[cfg(target_os = "linux")]
+fn add_watches(
+    inotify: &mut Inotify,
+    dir: PathBuf,
+    dirs: &mut HashMap<WatchDescriptor, PathBuf>,
+) -> io::Result<()> {
+    let wd = inotify.add_watch(
+        &dir,
+        WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MODIFY
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO,
+    )?;
+
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            add_watches(inotify, entry.path(), dirs)?;
+        }
+    }
+
+    dirs.insert(wd, dir);
+    Ok(())
+}